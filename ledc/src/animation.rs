@@ -0,0 +1,580 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{bail, Result};
+use eframe::{
+    egui::{ComboBox, DragValue, Pos2, ProgressBar, Sense, Slider, Ui},
+    epaint::{pos2, vec2, Color32, Shape, Stroke},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    controller::Controller, easing::Easing, Animation, AnimationKey, Playback, SharedAppData, Strip,
+};
+
+/// Anything shorter can't be edited or played back sensibly.
+const MIN_LENGTH: f32 = 0.1;
+/// How tall each channel's lane in the timeline is.
+const LANE_HEIGHT: f32 = 48.0;
+/// How close to a key the pointer has to be to pick it up, in points.
+const GRAB_RADIUS: f32 = 8.0;
+const MAX: f32 = u16::MAX as f32;
+
+/// The selected key in an editor: strip, channel and index.
+type Selection = Option<(usize, usize, usize)>;
+
+impl Animation {
+    pub fn dir() -> Result<PathBuf> {
+        Ok(SharedAppData::config_dir()?.join("animations"))
+    }
+
+    fn length(&self) -> f32 {
+        self.length.max(MIN_LENGTH)
+    }
+
+    /// Where playback is after `elapsed` seconds, in seconds from the start.
+    fn position(&self, elapsed: f64) -> f32 {
+        let length = f64::from(self.length());
+        let position = match self.playback {
+            Playback::Loop => elapsed % length,
+            Playback::PingPong => {
+                let t = elapsed % (2.0 * length);
+                if t <= length {
+                    t
+                } else {
+                    2.0 * length - t
+                }
+            }
+            Playback::Once => elapsed.min(length),
+        };
+        position as f32
+    }
+
+    /// Where playback is at `now`, if it's playing.
+    pub fn playhead(&self, now: SystemTime) -> Option<f32> {
+        let elapsed = now.duration_since(self.started_at?).unwrap_or_default();
+        Some(self.position(elapsed.as_secs_f64()))
+    }
+
+    fn finished(&self, now: SystemTime) -> bool {
+        self.playback == Playback::Once
+            && self.started_at.is_some_and(|started_at| {
+                let elapsed = now.duration_since(started_at).unwrap_or_default();
+                elapsed.as_secs_f32() >= self.length()
+            })
+    }
+
+    /// The strips `at` seconds in. Channels without keys stay as they are in `strips`.
+    fn frame(&self, at: f32, strips: &[Strip]) -> Vec<Strip> {
+        strips
+            .iter()
+            .enumerate()
+            .map(|(i, strip)| match self.tracks.get(i) {
+                Some([cold, warm]) => Strip(
+                    value_at(cold, at).unwrap_or(strip.0),
+                    value_at(warm, at).unwrap_or(strip.1),
+                ),
+                None => strip.clone(),
+            })
+            .collect()
+    }
+
+    /// Reads back what [`Animation::export`] wrote.
+    pub fn import(path: &Path) -> Result<Self> {
+        let mut animation: Animation = serde_json::from_str(&fs::read_to_string(path)?)?;
+        for keys in animation.tracks.iter_mut().flatten() {
+            if keys.iter().any(|key| !key.at.is_finite()) {
+                bail!("{} has a key at an impossible time", path.display());
+            }
+            keys.sort_by(|a, b| a.at.total_cmp(&b.at));
+        }
+        Ok(animation)
+    }
+
+    /// Writes it to [`Animation::dir`] as JSON, returning where.
+    pub fn export(&self) -> Result<PathBuf> {
+        self.export_to(&Self::dir()?)
+    }
+
+    fn export_to(&self, dir: &Path) -> Result<PathBuf> {
+        if self.name.is_empty() || self.name.contains(['/', '\0']) {
+            bail!("{:?} won't do as a file name", self.name);
+        }
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.json", self.name));
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+
+    /// The timeline: a lane per channel with its keys and the curve between them, and the
+    /// selected key's settings underneath. Returns whether anything changed.
+    ///
+    /// Clicking or dragging on a lane adds a key or moves the one under the pointer, right
+    /// clicking removes it.
+    pub fn editor(&mut self, ui: &mut Ui, playhead: Option<f32>) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            changed |= ui
+                .add(
+                    DragValue::new(&mut self.length)
+                        .clamp_range(MIN_LENGTH..=86_400.0)
+                        .speed(0.1)
+                        .suffix(" s"),
+                )
+                .changed();
+            for (playback, name) in [
+                (Playback::Loop, "loop"),
+                (Playback::PingPong, "ping-pong"),
+                (Playback::Once, "once"),
+            ] {
+                changed |= ui.radio_value(&mut self.playback, playback, name).changed();
+            }
+        });
+
+        let id = ui.make_persistent_id("animation_selection");
+        let mut selection = ui
+            .data()
+            .get_temp::<Selection>(id)
+            .flatten()
+            // Its key might have gone since.
+            .filter(|&(i, channel, index)| {
+                self.tracks
+                    .get(i)
+                    .is_some_and(|track| index < track[channel].len())
+            });
+        let length = self.length();
+        for (i, track) in self.tracks.iter_mut().enumerate() {
+            for (channel, keys) in track.iter_mut().enumerate() {
+                let name = if channel == 0 { "cold" } else { "warm" };
+                ui.label(format!("Strip {i} {name}"));
+                changed |= lane(ui, keys, (i, channel), &mut selection, length, playhead);
+            }
+        }
+
+        if let Some((i, channel, index)) = selection {
+            let keys = &mut self.tracks[i][channel];
+            let (before, after) = neighbours(keys, index);
+            let key = &mut keys[index];
+            let mut remove = false;
+            ui.horizontal(|ui| {
+                let name = if channel == 0 { "cold" } else { "warm" };
+                ui.label(format!("Strip {i} {name} key {index}"));
+                changed |= ui
+                    .add(
+                        DragValue::new(&mut key.at)
+                            .clamp_range(before..=after)
+                            .speed(0.05)
+                            .suffix(" s"),
+                    )
+                    .changed();
+                changed |= ui.add(Slider::new(&mut key.value, 0..=u16::MAX)).changed();
+                ComboBox::from_id_source("key_easing")
+                    .selected_text(key.easing.name())
+                    .show_ui(ui, |ui| {
+                        for easing in Easing::ALL {
+                            changed |= ui
+                                .selectable_value(&mut key.easing, easing, easing.name())
+                                .changed();
+                        }
+                    });
+                ui.label("to the next");
+                remove = ui.small_button("remove").clicked();
+            });
+            if remove {
+                keys.remove(index);
+                selection = None;
+                changed = true;
+            }
+        }
+        ui.data().insert_temp(id, selection);
+
+        changed
+    }
+}
+
+/// The value of a channel `at` seconds in, `None` if it has no keys.
+fn value_at(keys: &[AnimationKey], at: f32) -> Option<u16> {
+    match keys.iter().position(|key| key.at > at) {
+        None => keys.last().map(|key| key.value),
+        Some(0) => Some(keys[0].value),
+        Some(next) => {
+            let (from, to) = (&keys[next - 1], &keys[next]);
+            let t = (at - from.at) / (to.at - from.at);
+            Some(from.easing.interpolate(from.value, to.value, t))
+        }
+    }
+}
+
+/// How far key `index` can move without overtaking another.
+fn neighbours(keys: &[AnimationKey], index: usize) -> (f32, f32) {
+    let before = index.checked_sub(1).map_or(0.0, |before| keys[before].at);
+    let after = keys.get(index + 1).map_or(f32::MAX, |after| after.at);
+    (before, after)
+}
+
+/// One channel's keys on the timeline. Returns whether any changed.
+fn lane(
+    ui: &mut Ui,
+    keys: &mut Vec<AnimationKey>,
+    (strip, channel): (usize, usize),
+    selection: &mut Selection,
+    length: f32,
+    playhead: Option<f32>,
+) -> bool {
+    let (response, painter) = ui.allocate_painter(
+        vec2(ui.available_width(), LANE_HEIGHT),
+        Sense::click_and_drag(),
+    );
+    let rect = response.rect;
+    let to_screen = |key: &AnimationKey| {
+        pos2(
+            rect.left() + key.at / length * rect.width(),
+            rect.bottom() - f32::from(key.value) / MAX * rect.height(),
+        )
+    };
+    let from_screen = |pos: Pos2| {
+        let at = ((pos.x - rect.left()) / rect.width()).clamp(0.0, 1.0) * length;
+        let value = ((rect.bottom() - pos.y) / rect.height()).clamp(0.0, 1.0) * MAX;
+        (at, value as u16)
+    };
+    let under = |keys: &[AnimationKey], pos: Pos2| {
+        keys.iter()
+            .enumerate()
+            .map(|(i, key)| (i, to_screen(key).distance(pos)))
+            .filter(|(_, distance)| *distance <= GRAB_RADIUS)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+    };
+    let mut changed = false;
+
+    // Picks up the key under `pos`, or puts a new one there.
+    let mut grab = |keys: &mut Vec<AnimationKey>, pos: Pos2| {
+        let index = under(keys, pos).unwrap_or_else(|| {
+            let (at, value) = from_screen(pos);
+            let index = keys.partition_point(|key| key.at <= at);
+            let easing = index
+                .checked_sub(1)
+                .map_or(Easing::Linear, |before| keys[before].easing);
+            keys.insert(index, AnimationKey { at, value, easing });
+            changed = true;
+            index
+        });
+        *selection = Some((strip, channel, index));
+    };
+    if response.drag_started() {
+        if let Some(pos) = ui.input().pointer.press_origin() {
+            grab(keys, pos);
+        }
+    } else if response.clicked() {
+        if let Some(pos) = response.interact_pointer_pos() {
+            grab(keys, pos);
+        }
+    } else if response.secondary_clicked() {
+        if let Some(index) = response
+            .interact_pointer_pos()
+            .and_then(|pos| under(keys, pos))
+        {
+            keys.remove(index);
+            if selection.is_some_and(|(s, c, _)| (s, c) == (strip, channel)) {
+                *selection = None;
+            }
+            changed = true;
+        }
+    }
+    if let (true, Some(pos), Some((s, c, index))) = (
+        response.dragged(),
+        response.interact_pointer_pos(),
+        *selection,
+    ) {
+        if (s, c) == (strip, channel) {
+            let (before, after) = neighbours(keys, index);
+            let (at, value) = from_screen(pos);
+            keys[index].at = at.clamp(before, after);
+            keys[index].value = value;
+            changed = true;
+        }
+    }
+
+    let visuals = ui.visuals();
+    painter.rect_stroke(rect, 2.0, visuals.widgets.noninteractive.bg_stroke);
+    let colour = if channel == 0 {
+        Color32::from_rgb(150, 190, 255)
+    } else {
+        Color32::from_rgb(255, 180, 90)
+    };
+    if !keys.is_empty() {
+        let steps = rect.width().max(2.0) as usize;
+        let points = (0..=steps)
+            .map(|step| {
+                let at = step as f32 / steps as f32 * length;
+                let value = value_at(keys, at).unwrap_or_default();
+                to_screen(&AnimationKey {
+                    at,
+                    value,
+                    easing: Easing::Linear,
+                })
+            })
+            .collect();
+        painter.add(Shape::line(points, Stroke::new(1.5, colour)));
+    }
+    for (i, key) in keys.iter().enumerate() {
+        let centre = to_screen(key);
+        if *selection == Some((strip, channel, i)) {
+            painter.circle(centre, 5.0, colour, visuals.selection.stroke);
+        } else {
+            painter.circle_filled(centre, 3.5, colour);
+        }
+    }
+    if let Some(at) = playhead {
+        let x = rect.left() + at / length * rect.width();
+        painter.line_segment(
+            [pos2(x, rect.top()), pos2(x, rect.bottom())],
+            Stroke::new(1.0, Color32::LIGHT_RED),
+        );
+    }
+
+    changed
+}
+
+impl SharedAppData {
+    /// Starts the animation at `index` from the top, on the lights.
+    pub fn play(&mut self, index: usize, now: SystemTime) {
+        let animation = &mut self.animations[index];
+        animation.started_at = Some(now);
+        self.controller = Box::new(Player::new(animation.clone()));
+    }
+
+    /// Whether the animation at `index` is the one on the lights.
+    pub fn playing(&self, index: usize) -> bool {
+        self.controller.name() == "Animation"
+            && self.controller.label() == self.animations[index].name
+    }
+}
+
+/// Plays back a copy of one of the [`Animation`]s, see [`SharedAppData::play`].
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Player {
+    animation: Animation,
+}
+
+impl Player {
+    pub fn new(animation: Animation) -> Self {
+        Player { animation }
+    }
+}
+
+impl Controller for Player {
+    fn name(&self) -> &'static str {
+        "Animation"
+    }
+
+    fn label(&self) -> String {
+        self.animation.name.clone()
+    }
+
+    fn output(&mut self, at: SystemTime, strips: &[Strip], _relay: bool) -> Option<Vec<Strip>> {
+        if self.animation.tracks.is_empty() {
+            return None;
+        }
+        // Picked up from the config, start it over.
+        self.animation.started_at.get_or_insert(at);
+        let position = self.animation.playhead(at)?;
+        Some(self.animation.frame(position, strips))
+    }
+
+    fn realtime(&self) -> bool {
+        !self.animation.finished(SystemTime::now())
+    }
+
+    fn params(&self) -> Vec<(&'static str, String)> {
+        let keys = self
+            .animation
+            .tracks
+            .iter()
+            .flatten()
+            .map(Vec::len)
+            .sum::<usize>();
+        vec![
+            ("name", self.animation.name.clone()),
+            ("length", self.animation.length.to_string()),
+            ("playback", format!("{:?}", self.animation.playback)),
+            ("keys", keys.to_string()),
+        ]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.group(|ui| {
+            ui.label(format!(
+                "Animation {}, edit it under Animations",
+                self.animation.name
+            ));
+            let playhead = self
+                .animation
+                .playhead(SystemTime::now())
+                .unwrap_or_default();
+            ui.add(
+                ProgressBar::new(playhead / self.animation.length())
+                    .text(format!("{playhead:.1} s")),
+            );
+        });
+    }
+
+    fn save(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+}
+
+/// `name`, or with a number after it if that's taken.
+pub fn unique_name(animations: &[Animation], name: &str) -> String {
+    let taken = |name: &str| animations.iter().any(|animation| animation.name == name);
+    if !taken(name) {
+        return name.to_string();
+    }
+    (2..)
+        .map(|n| format!("{name} {n}"))
+        .find(|name| !taken(name))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, time::Duration};
+
+    use super::*;
+
+    fn key(at: f32, value: u16) -> AnimationKey {
+        AnimationKey {
+            at,
+            value,
+            easing: Easing::Linear,
+        }
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("ledc-animation-{name}-{}", std::process::id()))
+    }
+
+    #[test]
+    fn interpolates_between_keys() {
+        let keys = [key(1.0, 1000), key(3.0, 3000), key(4.0, 0)];
+        assert_eq!(value_at(&[], 1.0), None);
+        // Held before the first key and after the last.
+        assert_eq!(value_at(&keys, 0.0), Some(1000));
+        assert_eq!(value_at(&keys, 10.0), Some(0));
+        assert_eq!(value_at(&keys, 1.0), Some(1000));
+        assert_eq!(value_at(&keys, 2.0), Some(2000));
+        assert_eq!(value_at(&keys, 3.0), Some(3000));
+        assert_eq!(value_at(&keys, 3.75), Some(750));
+
+        // Each key's easing takes it to the next one.
+        let mut eased = keys;
+        eased[0].easing = Easing::EaseInOut;
+        let early = value_at(&eased, 1.5).unwrap();
+        assert!(early < 1500, "{early}");
+        assert_eq!(value_at(&eased, 2.0), Some(2000));
+        assert_eq!(value_at(&eased, 3.5), Some(1500));
+
+        // Two keys at once jump straight to the second.
+        let jump = [key(0.0, 0), key(1.0, 100), key(1.0, 900)];
+        assert_eq!(value_at(&jump, 1.0), Some(900));
+        assert_eq!(value_at(&jump, 0.5), Some(50));
+    }
+
+    #[test]
+    fn frames_and_playback() {
+        let mut animation = Animation::new("a".to_string(), &[Strip(0, 0)]);
+        animation.length = 4.0;
+        animation.tracks[0][0] = vec![key(0.0, 0), key(4.0, 4000)];
+        animation.tracks[0][1].clear();
+        // The warm channel and the strip without a track keep what they had.
+        let strips = [Strip(7, 8), Strip(9, 10)];
+        assert_eq!(
+            animation.frame(1.0, &strips),
+            [Strip(1000, 8), Strip(9, 10)]
+        );
+
+        let positions = |animation: &Animation| [1.0, 5.0, 7.0].map(|t| animation.position(t));
+        assert_eq!(positions(&animation), [1.0, 1.0, 3.0]);
+        animation.playback = Playback::PingPong;
+        assert_eq!(positions(&animation), [1.0, 3.0, 1.0]);
+        animation.playback = Playback::Once;
+        assert_eq!(positions(&animation), [1.0, 4.0, 4.0]);
+
+        let start = SystemTime::UNIX_EPOCH;
+        animation.started_at = Some(start);
+        assert!(!animation.finished(start + Duration::from_secs(3)));
+        assert!(animation.finished(start + Duration::from_secs(4)));
+        assert_eq!(
+            animation.playhead(start + Duration::from_secs(9)),
+            Some(4.0)
+        );
+    }
+
+    #[test]
+    fn round_trips_through_export() {
+        let dir = scratch("export");
+        let mut animation = Animation::new("Sunrise".to_string(), &[Strip(1, 2), Strip(3, 4)]);
+        animation.playback = Playback::PingPong;
+        animation.tracks[1][0].push(AnimationKey {
+            at: 2.5,
+            value: u16::MAX,
+            easing: Easing::Perceptual,
+        });
+        animation.started_at = Some(SystemTime::now());
+
+        let path = animation.export_to(&dir).unwrap();
+        assert_eq!(path, dir.join("Sunrise.json"));
+        let back = Animation::import(&path).unwrap();
+        // Whether it was playing isn't saved.
+        animation.started_at = None;
+        assert_eq!(back, animation);
+
+        for name in ["", "a/b"] {
+            animation.name = name.to_string();
+            assert!(animation.export_to(&dir).is_err());
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sorts_imported_keys() {
+        let dir = scratch("import");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("edited.json");
+        fs::write(
+            &path,
+            r#"{
+                "name": "edited",
+                "length": 3.0,
+                "playback": "Once",
+                "tracks": [[
+                    [{"at": 2.0, "value": 200}, {"at": 0.0, "value": 0}, {"at": 1.0, "value": 100}],
+                    []
+                ]]
+            }"#,
+        )
+        .unwrap();
+        let animation = Animation::import(&path).unwrap();
+        let times: Vec<_> = animation.tracks[0][0].iter().map(|key| key.at).collect();
+        assert_eq!(times, [0.0, 1.0, 2.0]);
+        assert_eq!(value_at(&animation.tracks[0][0], 1.5), Some(150));
+
+        // JSON has no NaN, but it has numbers too big for an f32.
+        fs::write(
+            &path,
+            r#"{"name": "bad", "tracks": [[[{"at": 1e99, "value": 0}], []]]}"#,
+        )
+        .unwrap();
+        let err = Animation::import(&path).unwrap_err().to_string();
+        assert!(err.contains("impossible time"), "{err}");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,836 @@
+use std::{
+    collections::VecDeque,
+    f32::consts::TAU,
+    io::{self, Read},
+    mem,
+    process::{Child, Command, Stdio},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Result};
+use eframe::egui::{ComboBox, ProgressBar, Slider, TextEdit, Ui};
+use serde::{Deserialize, Serialize};
+
+use crate::{controller::Controller, simulate, SharedAppData, Strip};
+
+/// What raw PCM (from `parec` and stdin) is read as: mono s16le at this rate.
+pub const RATE: u32 = 44_100;
+
+/// Where the bass stops and the mids start, and where the mids give way to the treble, in Hz.
+const BASS_CUTOFF: f32 = 200.0;
+const TREBLE_CUTOFF: f32 = 2000.0;
+/// Beat detection looks at the bass in windows this long...
+const BEAT_WINDOW: Duration = Duration::from_millis(20);
+/// ...against the average of this many of them,
+const BEAT_HISTORY: usize = 50;
+/// and doesn't go off again within this long of the last one.
+const BEAT_HOLDOFF: Duration = Duration::from_millis(150);
+
+/// Samples at a rate, or why there won't be any more.
+type Chunk = Result<(u32, Vec<f32>), String>;
+
+/// Where the samples come from.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum Source {
+    /// A PulseAudio (or PipeWire) source, by way of `parec`. Empty means the default output's monitor.
+    Monitor(String),
+    /// Raw mono s16le at [`RATE`], like `parec --raw --channels=1 --format=s16le | ledc`.
+    Stdin,
+    /// A WAV file, played at its own pace.
+    Wav(String),
+}
+
+/// Part of the sound a channel follows.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum Band {
+    Off,
+    /// Everything.
+    Level,
+    Bass,
+    Mid,
+    Treble,
+    /// Flashes on each beat, then fades with the release.
+    Beat,
+}
+
+impl Band {
+    pub const ALL: [Band; 6] = [
+        Band::Off,
+        Band::Level,
+        Band::Bass,
+        Band::Mid,
+        Band::Treble,
+        Band::Beat,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Band::Off => "Off",
+            Band::Level => "Level",
+            Band::Bass => "Bass",
+            Band::Mid => "Mid",
+            Band::Treble => "Treble",
+            Band::Beat => "Beat",
+        }
+    }
+}
+
+/// What one channel of one strip follows.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct Mapping {
+    pub band: Band,
+    pub gain: f32,
+}
+
+/// Turns the band levels (and beats) of whatever's playing into brightness.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Audio {
+    pub source: Source,
+    /// How quickly the levels rise and fall, in ms.
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    /// How many times the last second's average bass energy makes a beat.
+    pub sensitivity: f32,
+    /// Cold and warm, for each strip.
+    pub mapping: Vec<[Mapping; 2]>,
+    /// The device or path while it's being typed. It only goes into `source` once the field
+    /// loses focus (or on Enter), so parec isn't restarted on every keystroke.
+    #[serde(skip)]
+    source_text: Option<String>,
+    #[serde(skip)]
+    stream: Option<Stream>,
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        let map = |band| Mapping { band, gain: 1.0 };
+        Audio {
+            source: Source::Monitor(String::new()),
+            attack_ms: 10.0,
+            release_ms: 300.0,
+            sensitivity: 1.5,
+            mapping: vec![
+                [map(Band::Treble), map(Band::Bass)],
+                [map(Band::Mid), map(Band::Beat)],
+            ],
+            source_text: None,
+            stream: None,
+        }
+    }
+}
+
+// Not the stream though, that's ours.
+impl Clone for Audio {
+    fn clone(&self) -> Self {
+        Audio {
+            source: self.source.clone(),
+            attack_ms: self.attack_ms,
+            release_ms: self.release_ms,
+            sensitivity: self.sensitivity,
+            mapping: self.mapping.clone(),
+            source_text: None,
+            stream: None,
+        }
+    }
+}
+
+/// Follows the band levels and finds the beats in a run of samples.
+///
+/// Works in sample time only, so the same samples always make the same levels.
+pub struct Analyzer {
+    rate: f32,
+    attack: f32,
+    release: f32,
+    sensitivity: f32,
+    /// One-pole lowpasses at the two cutoffs.
+    bass: f32,
+    below_treble: f32,
+    /// Level, bass, mid and treble envelopes, 0 to about 1.
+    envelopes: [f32; 4],
+    window: (f32, usize),
+    history: VecDeque<f32>,
+    since_beat: usize,
+    beat: f32,
+}
+
+impl Analyzer {
+    pub fn new(rate: u32, audio: &Audio) -> Self {
+        let mut analyzer = Analyzer {
+            rate: rate as f32,
+            attack: 0.0,
+            release: 0.0,
+            sensitivity: 0.0,
+            bass: 0.0,
+            below_treble: 0.0,
+            envelopes: [0.0; 4],
+            window: (0.0, 0),
+            history: VecDeque::with_capacity(BEAT_HISTORY),
+            since_beat: usize::MAX,
+            beat: 0.0,
+        };
+        analyzer.tune(audio);
+        analyzer
+    }
+
+    /// Picks up changed settings.
+    pub fn tune(&mut self, audio: &Audio) {
+        // How much of the old value is left after one sample.
+        let coefficient = |ms: f32| {
+            if ms <= 0.0 {
+                0.0
+            } else {
+                (-1000.0 / (ms * self.rate)).exp()
+            }
+        };
+        self.attack = coefficient(audio.attack_ms);
+        self.release = coefficient(audio.release_ms);
+        self.sensitivity = audio.sensitivity;
+    }
+
+    pub fn feed(&mut self, samples: &[f32]) {
+        let lowpass = |cutoff: f32| 1.0 - (-TAU * cutoff / self.rate).exp();
+        let (bass_k, treble_k) = (lowpass(BASS_CUTOFF), lowpass(TREBLE_CUTOFF));
+        let window = (BEAT_WINDOW.as_secs_f32() * self.rate) as usize;
+        let holdoff = (BEAT_HOLDOFF.as_secs_f32() * self.rate) as usize;
+
+        for &x in samples {
+            self.bass += bass_k * (x - self.bass);
+            self.below_treble += treble_k * (x - self.below_treble);
+            let bands = [
+                x,
+                self.bass,
+                self.below_treble - self.bass,
+                x - self.below_treble,
+            ];
+            for (envelope, band) in self.envelopes.iter_mut().zip(bands) {
+                // Rectified, scaled so a full-scale sine averages out at 1.
+                let target = band.abs() * std::f32::consts::FRAC_PI_2;
+                let k = if target > *envelope {
+                    self.attack
+                } else {
+                    self.release
+                };
+                *envelope = target + (*envelope - target) * k;
+            }
+
+            self.beat *= self.release;
+            self.since_beat = self.since_beat.saturating_add(1);
+            self.window.0 += self.bass * self.bass;
+            self.window.1 += 1;
+            if self.window.1 >= window {
+                let energy = self.window.0 / self.window.1 as f32;
+                self.window = (0.0, 0);
+                let average = self.history.iter().sum::<f32>() / self.history.len().max(1) as f32;
+                // Wait for a bit of history, and ignore the noise floor.
+                if self.history.len() >= BEAT_HISTORY / 4
+                    && energy > average * self.sensitivity
+                    && energy > 1e-4
+                    && self.since_beat >= holdoff
+                {
+                    self.beat = 1.0;
+                    self.since_beat = 0;
+                }
+                if self.history.len() == BEAT_HISTORY {
+                    self.history.pop_front();
+                }
+                self.history.push_back(energy);
+            }
+        }
+    }
+
+    /// Where `band` is at, from 0 to about 1.
+    pub fn level(&self, band: Band) -> f32 {
+        match band {
+            Band::Off => 0.0,
+            Band::Level => self.envelopes[0],
+            Band::Bass => self.envelopes[1],
+            Band::Mid => self.envelopes[2],
+            Band::Treble => self.envelopes[3],
+            Band::Beat => self.beat,
+        }
+    }
+
+    /// The strips for where it's at, one per mapping.
+    pub fn frame(&self, mapping: &[[Mapping; 2]]) -> Vec<Strip> {
+        let value = |map: &Mapping| {
+            ((self.level(map.band) * map.gain).clamp(0.0, 1.0) * f32::from(u16::MAX)) as u16
+        };
+        mapping
+            .iter()
+            .map(|[cold, warm]| Strip(value(cold), value(warm)))
+            .collect()
+    }
+}
+
+/// A source being listened to, on a thread of its own.
+struct Stream {
+    source: Source,
+    rx: Receiver<Chunk>,
+    /// `parec`, if that's where it's coming from.
+    child: Option<Child>,
+    analyzer: Option<Analyzer>,
+    status: String,
+}
+
+impl Stream {
+    fn open(source: &Source) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let mut stream = Stream {
+            source: source.clone(),
+            rx,
+            child: None,
+            analyzer: None,
+            status: "listening".into(),
+        };
+        match source {
+            Source::Monitor(device) => {
+                let device = if device.is_empty() {
+                    "@DEFAULT_MONITOR@"
+                } else {
+                    device
+                };
+                let child = Command::new("parec")
+                    .args([
+                        "--raw",
+                        "--format=s16le",
+                        "--channels=1",
+                        &format!("--rate={RATE}"),
+                        &format!("--device={device}"),
+                    ])
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::null())
+                    .spawn();
+                match child {
+                    Ok(mut child) => {
+                        if let Some(stdout) = child.stdout.take() {
+                            thread::spawn(move || read_raw(stdout, |chunk| tx.send(chunk).is_ok()));
+                        }
+                        stream.child = Some(child);
+                    }
+                    Err(err) => stream.status = format!("couldn't run parec: {err}"),
+                }
+            }
+            Source::Stdin => listen_stdin(tx),
+            Source::Wav(path) => match read_wav(path) {
+                Ok((rate, samples)) => {
+                    thread::spawn(move || play(rate, &samples, &tx));
+                }
+                Err(err) => stream.status = format!("{err:#}"),
+            },
+        }
+        stream
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            child.kill().ok();
+            child.wait().ok();
+        }
+    }
+}
+
+/// Hands mono s16le from `reader` to `send` in chunks, until either the input ends or
+/// `send` says to stop.
+fn read_raw(mut reader: impl Read, mut send: impl FnMut(Chunk) -> bool) {
+    let mut buf = vec![0u8; 2 * RATE as usize / 100];
+    loop {
+        if let Err(err) = reader.read_exact(&mut buf) {
+            send(Err(format!("input ended: {err}")));
+            return;
+        }
+        let samples = buf
+            .chunks_exact(2)
+            .map(|pair| f32::from(i16::from_le_bytes([pair[0], pair[1]])) / 32768.0)
+            .collect();
+        if !send(Ok((RATE, samples))) {
+            return;
+        }
+    }
+}
+
+/// Stdin can only be read the once, so a single reader serves whichever stream is
+/// listening to it at the time.
+struct StdinTap {
+    tx: Option<Sender<Chunk>>,
+    reading: bool,
+    /// Why it stopped, once it has.
+    ended: Option<String>,
+}
+
+static STDIN: Mutex<StdinTap> = Mutex::new(StdinTap {
+    tx: None,
+    reading: false,
+    ended: None,
+});
+
+/// Has stdin's samples go to `tx` from now on, instead of the last stream's.
+fn listen_stdin(tx: Sender<Chunk>) {
+    let mut tap = STDIN.lock().unwrap();
+    if let Some(err) = &tap.ended {
+        tx.send(Err(err.clone())).ok();
+        return;
+    }
+    tap.tx = Some(tx);
+    if !mem::replace(&mut tap.reading, true) {
+        thread::spawn(|| {
+            read_raw(io::stdin().lock(), |chunk| {
+                let mut tap = STDIN.lock().unwrap();
+                if let Err(err) = &chunk {
+                    tap.ended = Some(err.clone());
+                }
+                if tap.tx.as_ref().is_some_and(|tx| tx.send(chunk).is_err()) {
+                    tap.tx = None;
+                }
+                // Nobody listening is no reason to stop, someone might be later.
+                true
+            })
+        });
+    }
+}
+
+/// Sends `samples` along as if they were coming in live.
+fn play(rate: u32, samples: &[f32], tx: &Sender<Chunk>) {
+    let started = Instant::now();
+    let chunk = (rate as usize / 100).max(1);
+    for (i, samples) in samples.chunks(chunk).enumerate() {
+        let due = Duration::from_secs_f64((i * chunk) as f64 / f64::from(rate));
+        thread::sleep(due.saturating_sub(started.elapsed()));
+        if tx.send(Ok((rate, samples.to_vec()))).is_err() {
+            return;
+        }
+    }
+    tx.send(Err("file ended".into())).ok();
+}
+
+/// The sample rate and samples of a WAV file, mixed down to mono from -1 to 1.
+pub fn read_wav(path: &str) -> Result<(u32, Vec<f32>)> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let interleaved = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|sample| sample as f32 / scale))
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+    let channels = usize::from(spec.channels.max(1));
+    let mono = interleaved
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    Ok((spec.sample_rate, mono))
+}
+
+impl Audio {
+    /// What the strips would do over `samples`, a frame every `1 / fps` seconds, same as live.
+    pub fn render(&self, rate: u32, samples: &[f32], fps: f32) -> Vec<Vec<Strip>> {
+        let mut analyzer = Analyzer::new(rate, self);
+        let step = ((rate as f32 / fps) as usize).max(1);
+        samples
+            .chunks(step)
+            .map(|chunk| {
+                analyzer.feed(chunk);
+                analyzer.frame(&self.mapping)
+            })
+            .collect()
+    }
+}
+
+impl Controller for Audio {
+    fn name(&self) -> &'static str {
+        "Audio"
+    }
+
+    fn output(&mut self, _at: SystemTime, strips: &[Strip], _relay: bool) -> Option<Vec<Strip>> {
+        let mut stream = match self.stream.take() {
+            Some(stream) if stream.source == self.source => stream,
+            _ => Stream::open(&self.source),
+        };
+        for chunk in stream.rx.try_iter() {
+            match chunk {
+                Ok((rate, samples)) => {
+                    let analyzer = match &mut stream.analyzer {
+                        Some(analyzer) if analyzer.rate == rate as f32 => analyzer,
+                        analyzer => analyzer.insert(Analyzer::new(rate, self)),
+                    };
+                    analyzer.tune(self);
+                    analyzer.feed(&samples);
+                }
+                Err(status) => stream.status = status,
+            }
+        }
+
+        let frame = stream
+            .analyzer
+            .as_ref()
+            .map(|analyzer| analyzer.frame(&self.mapping));
+        self.stream = Some(stream);
+        let mut strips = strips.to_vec();
+        for (strip, value) in strips.iter_mut().zip(frame?) {
+            *strip = value;
+        }
+        Some(strips)
+    }
+
+    fn realtime(&self) -> bool {
+        true
+    }
+
+    fn live(&self) -> bool {
+        true
+    }
+
+    fn params(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("source", format!("{:?}", self.source)),
+            ("attack_ms", self.attack_ms.to_string()),
+            ("release_ms", self.release_ms.to_string()),
+            ("sensitivity", self.sensitivity.to_string()),
+            ("mapping", format!("{:?}", self.mapping)),
+        ]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.group(|ui| {
+            ui.label("Audio");
+            ui.horizontal(|ui| {
+                let kinds = [
+                    ("Monitor", Source::Monitor(String::new())),
+                    ("Stdin", Source::Stdin),
+                    ("WAV file", Source::Wav(String::new())),
+                ];
+                for (name, source) in kinds {
+                    let selected = mem::discriminant(&self.source) == mem::discriminant(&source);
+                    if ui.radio(selected, name).clicked() && !selected {
+                        self.source = source;
+                        self.source_text = None;
+                    }
+                }
+                let (text, hint) = match &mut self.source {
+                    Source::Monitor(device) => (device, "default"),
+                    Source::Stdin => {
+                        ui.label(format!("mono s16le at {RATE} Hz"));
+                        return;
+                    }
+                    Source::Wav(path) => (path, "path"),
+                };
+                let mut editing = self.source_text.take().unwrap_or_else(|| text.clone());
+                let response = ui.add(TextEdit::singleline(&mut editing).hint_text(hint));
+                if response.lost_focus() {
+                    *text = editing;
+                } else if response.has_focus() {
+                    self.source_text = Some(editing);
+                }
+            });
+            if let Some(stream) = &self.stream {
+                ui.label(&stream.status);
+                if let Some(analyzer) = &stream.analyzer {
+                    ui.horizontal(|ui| {
+                        for band in &Band::ALL[1..] {
+                            ui.add(
+                                ProgressBar::new(analyzer.level(*band))
+                                    .desired_width(80.0)
+                                    .text(band.name()),
+                            );
+                        }
+                    });
+                }
+            }
+
+            ui.add(
+                Slider::new(&mut self.attack_ms, 0.0..=1000.0)
+                    .text("attack (ms)")
+                    .logarithmic(true),
+            );
+            ui.add(
+                Slider::new(&mut self.release_ms, 0.0..=5000.0)
+                    .text("release (ms)")
+                    .logarithmic(true),
+            );
+            ui.add(Slider::new(&mut self.sensitivity, 1.0..=4.0).text("beat sensitivity"));
+
+            ui.horizontal_wrapped(|ui| {
+                for (i, channels) in self.mapping.iter_mut().enumerate() {
+                    ui.push_id(("audio_strip", i), |ui| {
+                        ui.group(|ui| {
+                            ui.label(format!("Strip {i}"));
+                            for (map, name) in channels.iter_mut().zip(["cold", "warm"]) {
+                                ui.horizontal(|ui| {
+                                    ComboBox::from_id_source(name)
+                                        .selected_text(format!("{name}: {}", map.band.name()))
+                                        .show_ui(ui, |ui| {
+                                            for band in Band::ALL {
+                                                ui.selectable_value(
+                                                    &mut map.band,
+                                                    band,
+                                                    band.name(),
+                                                );
+                                            }
+                                        });
+                                    ui.add(Slider::new(&mut map.gain, 0.0..=4.0).text("gain"));
+                                });
+                            }
+                        });
+                    });
+                }
+            });
+        });
+    }
+
+    fn save(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+}
+
+/// `ledc audio FILE.wav [--fps N]`
+///
+/// Prints the frames the audio controller (as configured, or the defaults) makes of a WAV
+/// file as CSV, so it can be checked without any lights or sound around. The config is only
+/// read, never written.
+pub fn cli(args: &[String]) -> Result<()> {
+    let (mut path, mut fps) = (None, 100.0);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fps" => {
+                fps = args
+                    .next()
+                    .ok_or_else(|| anyhow!("--fps needs a value"))?
+                    .parse()?;
+            }
+            flag if flag.starts_with("--") => bail!("unknown flag {flag:?}"),
+            file => path = Some(file),
+        }
+    }
+    let path = path.ok_or_else(|| anyhow!("which WAV file?"))?;
+    if fps <= 0.0 {
+        bail!("--fps has to be more than zero");
+    }
+
+    let dat = SharedAppData::read_config()?.unwrap_or_default();
+    let audio = if dat.controller.name() == "Audio" {
+        serde_json::from_value(dat.controller.save())?
+    } else {
+        Audio::default()
+    };
+
+    let (rate, samples) = read_wav(path)?;
+    let frames = audio.render(rate, &samples, fps);
+    let strips = frames.first().map_or(0, Vec::len);
+    println!("seconds,{}", simulate::channels(strips).join(","));
+    for (i, frame) in frames.iter().enumerate() {
+        let values = frame
+            .iter()
+            .flat_map(|strip| [strip.0.to_string(), strip.1.to_string()])
+            .collect::<Vec<_>>();
+        println!("{:.3},{}", i as f32 / fps, values.join(","));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        f32::consts::{E, FRAC_PI_2},
+        fs,
+    };
+
+    use super::*;
+
+    const SECOND: usize = RATE as usize;
+
+    fn sine(hz: f32, amplitude: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| amplitude * (TAU * hz * i as f32 / RATE as f32).sin())
+            .collect()
+    }
+
+    /// Writes `samples` out as 16 bit WAV with `channels` copies of each, and reads it back.
+    fn through_wav(name: &str, channels: u16, samples: &[f32]) -> Vec<f32> {
+        let dir = env::temp_dir().join(format!("ledc-audio-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate: RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for &sample in samples {
+            for channel in 0..channels {
+                // Every other channel upside down, for mixing down.
+                let sample = if channel % 2 == 0 { sample } else { -sample };
+                writer.write_sample((sample * 32767.0) as i16).unwrap();
+            }
+        }
+        writer.finalize().unwrap();
+
+        let (rate, read) = read_wav(path.to_str().unwrap()).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(rate, RATE);
+        read
+    }
+
+    /// Smooths the same both ways, so the envelopes settle on the average.
+    fn steady() -> Audio {
+        Audio {
+            attack_ms: 200.0,
+            release_ms: 200.0,
+            ..Audio::default()
+        }
+    }
+
+    fn analyzed(audio: &Audio, samples: &[f32]) -> Analyzer {
+        let mut analyzer = Analyzer::new(RATE, audio);
+        analyzer.feed(samples);
+        analyzer
+    }
+
+    fn near(got: f32, want: f32, within: f32) {
+        assert!((got - want).abs() <= within, "{got} isn't about {want}");
+    }
+
+    #[test]
+    fn reads_wavs_as_mono() {
+        let samples = sine(440.0, 0.5, SECOND / 10);
+        let mono = through_wav("mono.wav", 1, &samples);
+        assert_eq!(mono.len(), samples.len());
+        for (got, want) in mono.iter().zip(&samples) {
+            near(*got, *want, 1e-4);
+        }
+        // Upside down on the right, so they cancel out.
+        let stereo = through_wav("stereo.wav", 2, &samples);
+        assert_eq!(stereo.len(), samples.len());
+        assert!(stereo.iter().all(|sample| sample.abs() < 1e-4));
+    }
+
+    #[test]
+    fn silence_is_dark() {
+        let analyzer = analyzed(
+            &Audio::default(),
+            &through_wav("silence.wav", 1, &[0.0; SECOND]),
+        );
+        for band in Band::ALL {
+            assert_eq!(analyzer.level(band), 0.0, "{}", band.name());
+        }
+        assert_eq!(
+            analyzer.frame(&Audio::default().mapping),
+            [Strip(0, 0), Strip(0, 0)]
+        );
+    }
+
+    #[test]
+    fn full_scale_sine_levels_out_at_one() {
+        let analyzer = analyzed(
+            &steady(),
+            &through_wav("sine.wav", 1, &sine(440.0, 1.0, SECOND)),
+        );
+        near(analyzer.level(Band::Level), 1.0, 0.05);
+        let half = analyzed(&steady(), &sine(440.0, 0.5, SECOND));
+        near(half.level(Band::Level), 0.5, 0.03);
+    }
+
+    #[test]
+    fn splits_the_bands() {
+        let bass = analyzed(
+            &steady(),
+            &through_wav("bass.wav", 1, &sine(50.0, 1.0, SECOND)),
+        );
+        assert!(bass.level(Band::Bass) > 0.8, "{}", bass.level(Band::Bass));
+        assert!(
+            bass.level(Band::Treble) < 0.1,
+            "{}",
+            bass.level(Band::Treble)
+        );
+
+        let mid = analyzed(&steady(), &sine(600.0, 1.0, SECOND));
+        assert!(mid.level(Band::Mid) > 0.5, "{}", mid.level(Band::Mid));
+        assert!(mid.level(Band::Mid) > mid.level(Band::Bass));
+        assert!(mid.level(Band::Mid) > mid.level(Band::Treble));
+
+        let treble = analyzed(&steady(), &sine(8000.0, 1.0, SECOND));
+        assert!(
+            treble.level(Band::Treble) > 0.8,
+            "{}",
+            treble.level(Band::Treble)
+        );
+        assert!(
+            treble.level(Band::Bass) < 0.05,
+            "{}",
+            treble.level(Band::Bass)
+        );
+    }
+
+    #[test]
+    fn decays_with_the_release() {
+        let audio = Audio {
+            attack_ms: 0.0,
+            release_ms: 300.0,
+            ..Audio::default()
+        };
+        let mut analyzer = analyzed(&audio, &[1.0; SECOND / 10]);
+        near(analyzer.level(Band::Level), FRAC_PI_2, 1e-3);
+
+        // Down to 1/e over the release.
+        analyzer.feed(&through_wav("decay.wav", 1, &vec![0.0; SECOND * 3 / 10]));
+        near(analyzer.level(Band::Level), FRAC_PI_2 / E, 0.01);
+        analyzer.feed(&vec![0.0; SECOND * 3]);
+        assert!(analyzer.level(Band::Level) < 1e-3);
+    }
+
+    #[test]
+    fn finds_the_beats() {
+        // A kick drum of sorts every half second, over a quiet hum.
+        let mut samples = sine(1000.0, 0.05, 4 * SECOND);
+        for beat in 0..8 {
+            let start = beat * SECOND / 2;
+            for (i, kick) in sine(60.0, 1.0, SECOND / 20).into_iter().enumerate() {
+                samples[start + i] += kick;
+            }
+        }
+        let samples = through_wav("beats.wav", 1, &samples);
+
+        let audio = Audio::default();
+        let mut analyzer = Analyzer::new(RATE, &audio);
+        let mut beats = 0;
+        let mut last = 0.0;
+        for chunk in samples.chunks(SECOND / 100) {
+            analyzer.feed(chunk);
+            let beat = analyzer.level(Band::Beat);
+            if beat > last + 0.5 {
+                beats += 1;
+            }
+            last = beat;
+        }
+        // The first one has no history to stand out against.
+        assert_eq!(beats, 7);
+    }
+
+    #[test]
+    fn renders_a_frame_per_step() {
+        let audio = Audio::default();
+        let frames = audio.render(RATE, &sine(440.0, 1.0, SECOND), 50.0);
+        assert_eq!(frames.len(), 50);
+        assert!(frames.iter().all(|frame| frame.len() == 2));
+        // Same samples, same frames.
+        assert_eq!(frames, audio.render(RATE, &sine(440.0, 1.0, SECOND), 50.0));
+    }
+}
@@ -0,0 +1,790 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use rrule::{RRule, RRuleSet, Unvalidated};
+
+use crate::{CalendarEdge, CalendarRule, SharedAppData};
+
+/// How far around now recurrences get expanded.
+const LOOKBEHIND: Duration = Duration::from_secs(2 * 86_400);
+const LOOKAHEAD: Duration = Duration::from_secs(8 * 86_400);
+/// How stale the expansion may get before we redo it, even if the file didn't change.
+const REFRESH: Duration = Duration::from_secs(86_400);
+
+/// One occurrence of a calendar event.
+#[derive(Debug, Clone)]
+pub struct Occurrence {
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub summary: String,
+    pub categories: Vec<String>,
+}
+
+/// A property line, e.g. `DTSTART;TZID=Europe/Amsterdam:20230714T090000`.
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Zone {
+    Utc,
+    Named(chrono_tz::Tz),
+    /// No timezone given, so it's whatever the local one is.
+    Floating,
+}
+
+impl Zone {
+    fn rrule(self) -> rrule::Tz {
+        match self {
+            Zone::Utc => rrule::Tz::UTC,
+            Zone::Named(tz) => rrule::Tz::Tz(tz),
+            Zone::Floating => rrule::Tz::LOCAL,
+        }
+    }
+}
+
+/// A DATE or DATE-TIME value, still in its own timezone.
+#[derive(Debug, Clone, Copy)]
+struct Stamp {
+    naive: NaiveDateTime,
+    zone: Zone,
+    all_day: bool,
+}
+
+impl Stamp {
+    fn parse(value: &str, tzid: Option<&str>) -> Result<Stamp> {
+        let value = value.trim();
+        if let Ok(date) = NaiveDate::parse_from_str(value, "%Y%m%d") {
+            return Ok(Stamp {
+                naive: date.and_hms_opt(0, 0, 0).unwrap_or_default(),
+                zone: Zone::Floating,
+                all_day: true,
+            });
+        }
+
+        let (value, utc) = match value.strip_suffix('Z') {
+            Some(value) => (value, true),
+            None => (value, false),
+        };
+        let naive = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+            .with_context(|| format!("bad date-time {value:?}"))?;
+        let zone = match (utc, tzid) {
+            (true, _) => Zone::Utc,
+            // Some exporters use Windows zone names, the best we can do there is local time.
+            (false, Some(tzid)) => chrono_tz::Tz::from_str(tzid.trim_matches('"'))
+                .map(Zone::Named)
+                .unwrap_or(Zone::Floating),
+            (false, None) => Zone::Floating,
+        };
+        Ok(Stamp {
+            naive,
+            zone,
+            all_day: false,
+        })
+    }
+
+    fn in_zone(self) -> Result<DateTime<rrule::Tz>> {
+        self.zone
+            .rrule()
+            .from_local_datetime(&self.naive)
+            .earliest()
+            .ok_or_else(|| anyhow!("{} doesn't exist in its timezone", self.naive))
+    }
+
+    fn instant(self) -> Result<SystemTime> {
+        Ok(self.in_zone()?.into())
+    }
+}
+
+/// Parses an RFC 5545 duration like `PT1H30M` or `P1W`.
+fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim().trim_start_matches('+');
+    if value.starts_with('-') {
+        bail!("negative durations aren't supported");
+    }
+    let mut rest = value
+        .strip_prefix('P')
+        .ok_or_else(|| anyhow!("bad duration {value:?}"))?;
+
+    let mut secs = 0;
+    let mut in_time = false;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('T') {
+            in_time = true;
+            rest = after;
+            continue;
+        }
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let n: u64 = rest[..digits]
+            .parse()
+            .with_context(|| format!("bad duration {value:?}"))?;
+        let unit = rest[digits..]
+            .chars()
+            .next()
+            .ok_or_else(|| anyhow!("bad duration {value:?}"))?;
+        let unit = match (in_time, unit) {
+            (false, 'W') => 7 * 86_400,
+            (false, 'D') => 86_400,
+            (true, 'H') => 3600,
+            (true, 'M') => 60,
+            (true, 'S') => 1,
+            _ => bail!("bad duration {value:?}"),
+        };
+        secs = n
+            .checked_mul(unit)
+            .and_then(|n| n.checked_add(secs))
+            .ok_or_else(|| anyhow!("duration {value:?} is too long"))?;
+        rest = &rest[digits + 1..];
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n' | 'N') => out.push('\n'),
+                Some(c) => out.push(c),
+                None => {}
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Splits the file into VEVENTs, each a list of its properties. Nested components
+/// (alarms and the like) are left out.
+fn events(ics: &str) -> Vec<Vec<Property>> {
+    // Long lines get folded by starting the continuation with whitespace.
+    let unfolded = ics
+        .replace("\r\n", "\n")
+        .replace("\n ", "")
+        .replace("\n\t", "");
+
+    let mut events = vec![];
+    let mut current: Option<Vec<Property>> = None;
+    let mut nested = 0;
+    for line in unfolded.lines() {
+        let Some((head, value)) = line.split_once(':') else {
+            continue;
+        };
+        let mut head = head.split(';');
+        let name = head.next().unwrap_or_default().to_ascii_uppercase();
+        let params = head
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        match (name.as_str(), value.trim().to_ascii_uppercase().as_str()) {
+            ("BEGIN", "VEVENT") if current.is_none() => current = Some(vec![]),
+            ("END", "VEVENT") if nested == 0 => events.extend(current.take()),
+            ("BEGIN", _) if current.is_some() => nested += 1,
+            ("END", _) if current.is_some() => nested -= 1,
+            _ => {
+                if let (Some(event), 0) = (&mut current, nested) {
+                    event.push(Property {
+                        name,
+                        params,
+                        value: value.to_string(),
+                    });
+                }
+            }
+        }
+    }
+    events
+}
+
+/// What [`parse`] made of a calendar.
+#[derive(Debug, Clone, Default)]
+pub struct Parsed {
+    pub occurrences: Vec<Occurrence>,
+    /// Events left out because they didn't parse, e.g. a bad DTSTART or RRULE.
+    pub skipped: usize,
+}
+
+/// Expands every event in `ics` that overlaps `from..to` into its occurrences, skipping
+/// the ones that don't parse rather than giving up on the whole calendar.
+pub fn parse(ics: &str, from: SystemTime, to: SystemTime) -> Parsed {
+    let events = events(ics);
+
+    // Moved or cancelled instances of recurring events, by UID and the start they replace.
+    let mut overridden = HashSet::new();
+    for event in &events {
+        let uid = event.iter().find(|p| p.name == "UID");
+        let recurrence_id = event.iter().find(|p| p.name == "RECURRENCE-ID");
+        // A bad RECURRENCE-ID gets its event skipped in `expand`.
+        if let (Some(uid), Some(Ok(instant))) = (uid, recurrence_id.map(recurrence_instant)) {
+            overridden.insert((uid.value.clone(), instant));
+        }
+    }
+
+    let mut parsed = Parsed::default();
+    for event in &events {
+        match expand(event, &overridden, from, to) {
+            Ok(occurrences) => parsed.occurrences.extend(occurrences),
+            Err(_) => parsed.skipped += 1,
+        }
+    }
+    parsed
+        .occurrences
+        .sort_by_key(|occurrence| occurrence.start);
+    parsed
+}
+
+fn recurrence_instant(id: &Property) -> Result<SystemTime> {
+    Stamp::parse(&id.value, id.param("TZID"))?.instant()
+}
+
+/// The occurrences of one event that overlap `from..to`.
+fn expand(
+    event: &[Property],
+    overridden: &HashSet<(String, SystemTime)>,
+    from: SystemTime,
+    to: SystemTime,
+) -> Result<Vec<Occurrence>> {
+    let get = |name: &str| event.iter().find(|p| p.name == name);
+    let all = |name: &'static str| event.iter().filter(move |p| p.name == name);
+
+    if get("STATUS").is_some_and(|p| p.value.eq_ignore_ascii_case("CANCELLED")) {
+        return Ok(vec![]);
+    }
+    let Some(dtstart) = get("DTSTART") else {
+        return Ok(vec![]);
+    };
+    let start = Stamp::parse(&dtstart.value, dtstart.param("TZID"))?;
+    let length = match (get("DTEND"), get("DURATION")) {
+        (Some(end), _) => {
+            let end = Stamp::parse(&end.value, end.param("TZID"))?.instant()?;
+            end.duration_since(start.instant()?).unwrap_or_default()
+        }
+        (None, Some(duration)) => parse_duration(&duration.value)?,
+        (None, None) if start.all_day => Duration::from_secs(86_400),
+        (None, None) => Duration::ZERO,
+    };
+    // Kept to what chrono can count, so the times around it can't overflow.
+    let too_long = || anyhow!("event is too long");
+    let span = chrono::Duration::from_std(length).map_err(|_| too_long())?;
+    let summary = get("SUMMARY")
+        .map(|p| unescape(&p.value))
+        .unwrap_or_default();
+    let categories = all("CATEGORIES")
+        .flat_map(|p| p.value.split(','))
+        .map(|category| unescape(category.trim()))
+        .collect::<Vec<_>>();
+    let uid = get("UID").map(|p| p.value.clone()).unwrap_or_default();
+
+    let mut starts = vec![];
+    if let Some(id) = get("RECURRENCE-ID") {
+        recurrence_instant(id)?;
+        starts.push(start.instant()?);
+    } else if get("RRULE").is_some() {
+        let dt_start = start.in_zone()?;
+        let mut set = RRuleSet::new(dt_start);
+        for rule in all("RRULE") {
+            let rule = RRule::<Unvalidated>::from_str(&rule.value)?;
+            set = set.rrule(rule.validate(dt_start)?);
+        }
+        for (name, add) in [("RDATE", true), ("EXDATE", false)] {
+            for property in all(name) {
+                for value in property.value.split(',') {
+                    let at = Stamp::parse(value, property.param("TZID"))?.in_zone()?;
+                    set = if add { set.rdate(at) } else { set.exdate(at) };
+                }
+            }
+        }
+
+        let tz = start.zone.rrule();
+        let after = DateTime::<Local>::from(from)
+            .checked_sub_signed(span)
+            .ok_or_else(too_long)?
+            .with_timezone(&tz);
+        let before = DateTime::<Local>::from(to).with_timezone(&tz);
+        starts.extend(
+            set.after(after)
+                .before(before)
+                .all(u16::MAX)
+                .dates
+                .into_iter()
+                .map(SystemTime::from)
+                .filter(|at| !overridden.contains(&(uid.clone(), *at))),
+        );
+    } else {
+        starts.push(start.instant()?);
+    }
+
+    let mut occurrences = vec![];
+    for start in starts {
+        let end = DateTime::<Utc>::from(start)
+            .checked_add_signed(span)
+            .ok_or_else(too_long)?;
+        let occurrence = Occurrence {
+            start,
+            end: end.into(),
+            summary: summary.clone(),
+            categories: categories.clone(),
+        };
+        if occurrence.end >= from && occurrence.start <= to {
+            occurrences.push(occurrence);
+        }
+    }
+    Ok(occurrences)
+}
+
+impl CalendarRule {
+    fn matches(&self, occurrence: &Occurrence) -> bool {
+        let needle = self.matching.trim().to_lowercase();
+        needle.is_empty()
+            || occurrence.summary.to_lowercase().contains(&needle)
+            || occurrence
+                .categories
+                .iter()
+                .any(|category| category.to_lowercase() == needle)
+    }
+
+    /// Every time this rule fires among `occurrences`.
+    pub fn fire_times(&self, occurrences: &[Occurrence]) -> Result<Vec<SystemTime>> {
+        let lead = humantime::parse_duration(&self.lead.0)?;
+        let matching = occurrences.iter().filter(|o| self.matches(o));
+        let edges = match self.edge {
+            CalendarEdge::Start => matching.map(|o| o.start).collect::<Vec<_>>(),
+            CalendarEdge::End => matching.map(|o| o.end).collect(),
+            CalendarEdge::LastEnd => {
+                let mut last = BTreeMap::new();
+                for occurrence in matching {
+                    let day = DateTime::<Local>::from(occurrence.end).date_naive();
+                    let end = last.entry(day).or_insert(occurrence.end);
+                    *end = occurrence.end.max(*end);
+                }
+                last.into_values().collect()
+            }
+        };
+        Ok(edges.into_iter().map(|edge| edge - lead).collect())
+    }
+}
+
+struct Loaded {
+    modified: Option<SystemTime>,
+    expanded_at: SystemTime,
+    parsed: Result<Parsed, String>,
+}
+
+/// Keeps the calendar files parsed and fires their rules, from the update thread.
+#[derive(Default)]
+pub struct Watcher {
+    files: HashMap<String, Loaded>,
+    last_check: Option<SystemTime>,
+}
+
+impl Watcher {
+    fn load(path: &str, now: SystemTime) -> Loaded {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        let parsed = fs::read_to_string(path)
+            .map(|ics| parse(&ics, now - LOOKBEHIND, now + LOOKAHEAD))
+            .map_err(|err| err.to_string());
+        Loaded {
+            modified,
+            expanded_at: now,
+            parsed,
+        }
+    }
+
+    fn parsed(&mut self, path: &str, now: SystemTime) -> &Result<Parsed, String> {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        let stale = self.files.get(path).is_none_or(|loaded| {
+            loaded.modified != modified
+                || now.duration_since(loaded.expanded_at).unwrap_or(REFRESH) >= REFRESH
+        });
+        if stale {
+            self.files.insert(path.to_string(), Self::load(path, now));
+        }
+        &self.files[path].parsed
+    }
+
+    /// Runs the actions of every rule whose time came since we last looked.
+    pub fn run_due(&mut self, dat: &mut SharedAppData, now: SystemTime) {
+        // Once a second is plenty, and the first look only sets the baseline.
+        let since = match self.last_check {
+            Some(last) if now.duration_since(last).unwrap_or_default() < Duration::from_secs(1) => {
+                return
+            }
+            Some(last) => last,
+            None => now,
+        };
+        self.last_check = Some(now);
+
+        let mut due = vec![];
+        for calendar in &mut dat.calendars {
+            if !calendar.enabled {
+                continue;
+            }
+
+            let parsed = match self.parsed(&calendar.path, now) {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    calendar.status = err.clone();
+                    continue;
+                }
+            };
+            calendar.status = format!(
+                "{} events from {} days back to {} days ahead",
+                parsed.occurrences.len(),
+                LOOKBEHIND.as_secs() / 86_400,
+                LOOKAHEAD.as_secs() / 86_400
+            );
+            if parsed.skipped > 0 {
+                calendar.status += &format!(", skipped {} that didn't parse", parsed.skipped);
+            }
+
+            calendar.upcoming.clear();
+            for (i, rule) in calendar.rules.iter_mut().enumerate() {
+                let times = rule.fire_times(&parsed.occurrences);
+                rule.lead.1 = times.as_ref().err().map(|_| ());
+                for at in times.unwrap_or_default() {
+                    if since < at && at <= now {
+                        due.push(rule.action.clone());
+                    } else if at > now {
+                        calendar.upcoming.push((at, i));
+                    }
+                }
+            }
+            calendar.upcoming.sort();
+        }
+
+        for action in due {
+            action.run(dat, now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timespec::tests::{at, in_berlin};
+
+    fn ics(lines: &[&str]) -> String {
+        let mut ics = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\n".to_string();
+        for line in lines {
+            ics += line;
+            ics += "\r\n";
+        }
+        ics + "END:VCALENDAR\r\n"
+    }
+
+    fn july(ics: &str) -> Parsed {
+        parse(ics, at("2023-07-01T00:00:00Z"), at("2023-07-31T00:00:00Z"))
+    }
+
+    fn starts(parsed: &Parsed) -> Vec<SystemTime> {
+        parsed.occurrences.iter().map(|o| o.start).collect()
+    }
+
+    const STANDUP: [&str; 5] = [
+        "UID:standup",
+        "SUMMARY:Standup",
+        "DTSTART;TZID=Europe/Amsterdam:20230703T090000",
+        "DTEND;TZID=Europe/Amsterdam:20230703T093000",
+        "RRULE:FREQ=WEEKLY;BYDAY=MO;COUNT=10",
+    ];
+
+    fn event(extra: &[&str]) -> Vec<String> {
+        let mut lines = vec!["BEGIN:VEVENT".to_string()];
+        lines.extend(STANDUP.iter().chain(extra).map(|line| line.to_string()));
+        lines.push("END:VEVENT".into());
+        lines
+    }
+
+    fn joined(events: &[Vec<String>]) -> String {
+        let lines = events.concat();
+        ics(&lines.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn expands_recurrences() {
+        let parsed = july(&joined(&[event(&[])]));
+        assert_eq!(parsed.skipped, 0);
+        assert_eq!(
+            starts(&parsed),
+            [
+                at("2023-07-03T07:00:00Z"),
+                at("2023-07-10T07:00:00Z"),
+                at("2023-07-17T07:00:00Z"),
+                at("2023-07-24T07:00:00Z"),
+            ]
+        );
+        let first = &parsed.occurrences[0];
+        assert_eq!(first.end, at("2023-07-03T07:30:00Z"));
+        assert_eq!(first.summary, "Standup");
+    }
+
+    #[test]
+    fn leaves_out_exdates() {
+        let parsed = july(&joined(&[event(&[
+            "EXDATE;TZID=Europe/Amsterdam:20230710T090000,20230724T090000",
+        ])]));
+        assert_eq!(
+            starts(&parsed),
+            [at("2023-07-03T07:00:00Z"), at("2023-07-17T07:00:00Z")]
+        );
+    }
+
+    #[test]
+    fn overrides_replace_their_instance() {
+        let moved = [
+            "BEGIN:VEVENT",
+            "UID:standup",
+            "RECURRENCE-ID;TZID=Europe/Amsterdam:20230717T090000",
+            "SUMMARY:Standup (moved)",
+            "DTSTART;TZID=Europe/Amsterdam:20230717T140000",
+            "DURATION:PT15M",
+            "END:VEVENT",
+        ];
+        let cancelled = [
+            "BEGIN:VEVENT",
+            "UID:standup",
+            "RECURRENCE-ID:20230724T070000Z",
+            "STATUS:CANCELLED",
+            "DTSTART:20230724T070000Z",
+            "END:VEVENT",
+        ];
+        let events = [
+            event(&[]),
+            moved.map(String::from).to_vec(),
+            cancelled.map(String::from).to_vec(),
+        ];
+        let parsed = july(&joined(&events));
+        assert_eq!(parsed.skipped, 0);
+        assert_eq!(
+            starts(&parsed),
+            [
+                at("2023-07-03T07:00:00Z"),
+                at("2023-07-10T07:00:00Z"),
+                at("2023-07-17T12:00:00Z"),
+            ]
+        );
+        let moved = &parsed.occurrences[2];
+        assert_eq!(moved.summary, "Standup (moved)");
+        assert_eq!(moved.end, at("2023-07-17T12:15:00Z"));
+    }
+
+    #[test]
+    fn all_day_events_are_local_days() {
+        in_berlin();
+        let parsed = july(&ics(&[
+            "BEGIN:VEVENT",
+            "SUMMARY:Holiday",
+            "DTSTART;VALUE=DATE:20230714",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "SUMMARY:Trip",
+            "DTSTART;VALUE=DATE:20230720",
+            "DTEND;VALUE=DATE:20230722",
+            "END:VEVENT",
+        ]));
+        let spans = parsed
+            .occurrences
+            .iter()
+            .map(|o| (o.start, o.end))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            spans,
+            [
+                (
+                    at("2023-07-14T00:00:00+02:00"),
+                    at("2023-07-15T00:00:00+02:00")
+                ),
+                (
+                    at("2023-07-20T00:00:00+02:00"),
+                    at("2023-07-22T00:00:00+02:00")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn recurrences_keep_to_their_timezone() {
+        in_berlin();
+        let parsed = parse(
+            &ics(&[
+                "BEGIN:VEVENT",
+                "DTSTART;TZID=America/New_York:20231103T090000",
+                "RRULE:FREQ=DAILY;COUNT=3",
+                "END:VEVENT",
+                "BEGIN:VEVENT",
+                "DTSTART;TZID=Not/AZone:20231103T090000",
+                "END:VEVENT",
+                "BEGIN:VEVENT",
+                "DTSTART:20231103T090000",
+                "END:VEVENT",
+            ]),
+            at("2023-11-01T00:00:00Z"),
+            at("2023-11-10T00:00:00Z"),
+        );
+        assert_eq!(
+            starts(&parsed),
+            [
+                // Unknown and missing zones are floating, so Berlin here.
+                at("2023-11-03T08:00:00Z"),
+                at("2023-11-03T08:00:00Z"),
+                at("2023-11-03T13:00:00Z"),
+                at("2023-11-04T13:00:00Z"),
+                at("2023-11-05T14:00:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_text_properties() {
+        let parsed = july(&ics(&[
+            "BEGIN:VEVENT",
+            "DTSTART:20230714T090000Z",
+            "DURATION:PT1H30M",
+            "SUMMARY:Planning\\, with a summary long enough",
+            "  to be folded",
+            "CATEGORIES:Work,Focus",
+            "CATEGORIES:Loud",
+            "BEGIN:VALARM",
+            "SUMMARY:Not this one",
+            "END:VALARM",
+            "END:VEVENT",
+        ]));
+        let event = &parsed.occurrences[0];
+        assert_eq!(
+            event.summary,
+            "Planning, with a summary long enough to be folded"
+        );
+        assert_eq!(event.categories, ["Work", "Focus", "Loud"]);
+        assert_eq!(event.end, at("2023-07-14T10:30:00Z"));
+    }
+
+    #[test]
+    fn skips_what_doesnt_parse() {
+        let parsed = july(&ics(&[
+            "BEGIN:VEVENT",
+            "DTSTART:sometime",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "DTSTART:20230714T090000Z",
+            "RRULE:FREQ=SOMETIMES",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "DTSTART:20230714T090000Z",
+            "DURATION:1 hour",
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "SUMMARY:Fine",
+            "DTSTART:20230714T090000Z",
+            "END:VEVENT",
+        ]));
+        assert_eq!(parsed.skipped, 3);
+        assert_eq!(parsed.occurrences.len(), 1);
+        assert_eq!(parsed.occurrences[0].summary, "Fine");
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(
+            parse_duration("P1W").unwrap(),
+            Duration::from_secs(7 * 86_400)
+        );
+        assert_eq!(
+            parse_duration("P1DT2H3M4S").unwrap(),
+            Duration::from_secs(86_400 + 2 * 3600 + 3 * 60 + 4)
+        );
+        assert_eq!(parse_duration("+PT15M").unwrap(), Duration::from_secs(900));
+        assert!(parse_duration("-PT15M").is_err());
+        assert!(parse_duration("P1H").is_err());
+        assert!(parse_duration("PT1").is_err());
+        let err = parse_duration("P99999999999999W").unwrap_err().to_string();
+        assert!(err.contains("too long"), "{err}");
+        assert!(parse_duration("P1DT18446744073709551615S").is_err());
+    }
+
+    #[test]
+    fn skips_events_too_long_to_count() {
+        // 1e14 seconds parses, but is millions of years.
+        let long = "DURATION:PT100000000000000S";
+        let parsed = july(&ics(&[
+            "BEGIN:VEVENT",
+            "DTSTART:20230714T090000Z",
+            "DURATION:P99999999999999W",
+            "END:VEVENT",
+            // Reaches back too far from the window.
+            "BEGIN:VEVENT",
+            "DTSTART:20230714T090000Z",
+            "RRULE:FREQ=DAILY",
+            long,
+            "END:VEVENT",
+            // Ends too far ahead.
+            "BEGIN:VEVENT",
+            "DTSTART:20230714T090000Z",
+            long,
+            "END:VEVENT",
+            "BEGIN:VEVENT",
+            "SUMMARY:Fine",
+            "DTSTART:20230714T090000Z",
+            "DURATION:P1W",
+            "END:VEVENT",
+        ]));
+        assert_eq!(parsed.skipped, 3);
+        assert_eq!(parsed.occurrences.len(), 1);
+        assert_eq!(parsed.occurrences[0].end, at("2023-07-21T09:00:00Z"));
+    }
+
+    #[test]
+    fn rules_fire_around_matching_events() {
+        in_berlin();
+        let parsed = july(&joined(&[
+            event(&["CATEGORIES:Meetings"]),
+            vec![
+                "BEGIN:VEVENT".into(),
+                "SUMMARY:Review".into(),
+                "DTSTART:20230703T100000Z".into(),
+                "DTEND:20230703T110000Z".into(),
+                "END:VEVENT".into(),
+            ],
+        ]));
+
+        let rule = CalendarRule {
+            matching: "meetings".into(),
+            ..CalendarRule::new()
+        };
+        assert_eq!(
+            rule.fire_times(&parsed.occurrences).unwrap()[..2],
+            [at("2023-07-03T06:50:00Z"), at("2023-07-10T06:50:00Z")]
+        );
+
+        // The last end of each day, whatever matches.
+        let rule = CalendarRule {
+            edge: CalendarEdge::LastEnd,
+            lead: ("0s".into(), None),
+            ..CalendarRule::new()
+        };
+        assert_eq!(
+            rule.fire_times(&parsed.occurrences).unwrap()[..2],
+            [at("2023-07-03T11:00:00Z"), at("2023-07-10T07:30:00Z")]
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    io::{BufRead, BufReader},
+    process::{Command, Stdio},
+    sync::mpsc::Sender,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::SharedAppData;
+
+/// Where the update thread gets its time from, so suspends and clock changes can be faked.
+pub trait Clock: Send {
+    /// Wall-clock time. Jumps when the clock gets set, and keeps counting through suspend.
+    fn now(&self) -> SystemTime;
+    /// Only ever moves forward, and stands still while suspended.
+    fn monotonic(&self) -> Duration;
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn monotonic(&self) -> Duration {
+        // CLOCK_MONOTONIC on Linux, which doesn't count suspend.
+        self.start.elapsed()
+    }
+}
+
+/// How far the wall clock moved apart from the monotonic one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jump {
+    /// Resumed from suspend, or the clock was set ahead.
+    Forward(Duration),
+    /// The clock was set back.
+    Backward(Duration),
+}
+
+/// Notices the wall clock and the monotonic clock disagreeing, which is what suspends
+/// and clock changes look like from in here.
+#[derive(Default)]
+pub struct JumpDetector {
+    last: Option<(SystemTime, Duration)>,
+}
+
+impl JumpDetector {
+    /// How much they can disagree by before it counts, the loop can be slow to come around.
+    const TOLERANCE: Duration = Duration::from_secs(2);
+
+    pub fn check(&mut self, clock: &dyn Clock) -> Option<Jump> {
+        let (now, monotonic) = (clock.now(), clock.monotonic());
+        let jump = self.last.and_then(|(then, then_monotonic)| {
+            let expected = monotonic.saturating_sub(then_monotonic);
+            match now.duration_since(then) {
+                Ok(real) if real > expected + Self::TOLERANCE => {
+                    Some(Jump::Forward(real - expected))
+                }
+                Ok(real) if real + Self::TOLERANCE < expected => {
+                    Some(Jump::Backward(expected - real))
+                }
+                Err(err) if err.duration() + expected > Self::TOLERANCE => {
+                    Some(Jump::Backward(err.duration() + expected))
+                }
+                _ => None,
+            }
+        });
+        self.last = Some((now, monotonic));
+        jump
+    }
+}
+
+/// logind's PrepareForSleep signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sleep {
+    Suspending,
+    Resumed,
+}
+
+impl Sleep {
+    /// Picks the signal out of a line of `gdbus monitor` output, like
+    /// `/org/freedesktop/login1: org.freedesktop.login1.Manager.PrepareForSleep (true,)`.
+    pub fn parse(line: &str) -> Option<Sleep> {
+        let (_, args) = line.split_once(".PrepareForSleep (")?;
+        match args.split([',', ')']).next()?.trim() {
+            "true" => Some(Sleep::Suspending),
+            "false" => Some(Sleep::Resumed),
+            _ => None,
+        }
+    }
+}
+
+/// Forwards logind's sleep signals into `tx` from a background thread, by way of
+/// `gdbus monitor` so we don't need a D-Bus library.
+///
+/// Without gdbus or a system bus this quietly does nothing, and resumes only get noticed
+/// by the [`JumpDetector`].
+pub fn watch_logind(tx: Sender<Sleep>) {
+    let child = Command::new("gdbus")
+        .args([
+            "monitor",
+            "--system",
+            "--dest",
+            "org.freedesktop.login1",
+            "--object-path",
+            "/org/freedesktop/login1",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let Some(stdout) = child.ok().and_then(|mut child| child.stdout.take()) else {
+        eprintln!("couldn't watch logind for suspends, falling back to watching the clock");
+        return;
+    };
+
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if let Some(sleep) = Sleep::parse(&line) {
+                if tx.send(sleep).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+impl SharedAppData {
+    /// Forgets everything worked out in wall-clock time, for after a suspend or clock change.
+    ///
+    /// Missed triggers aren't made up for, lights-off at 22:00 has no business firing at 08:00.
+    pub fn forget_wall_clock(&mut self, now: SystemTime) {
+        for trigger in &mut self.triggers {
+            trigger.next = None;
+        }
+        // Recurring schedules that haven't begun yet re-arm themselves from now on.
+        for schedule in &mut self.schedules {
+            if schedule.recurrence.enabled && !matches!(schedule.begins_at(), Ok(at) if at <= now) {
+                schedule.send = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::{timespec::tests::at, Trigger};
+
+    /// Only moves when told to.
+    pub struct FakeClock {
+        now: Cell<SystemTime>,
+        monotonic: Cell<Duration>,
+    }
+
+    impl FakeClock {
+        pub fn new(now: SystemTime) -> Self {
+            FakeClock {
+                now: Cell::new(now),
+                monotonic: Cell::new(Duration::ZERO),
+            }
+        }
+
+        /// Time passing normally.
+        pub fn tick(&self, by: Duration) {
+            self.now.set(self.now.get() + by);
+            self.monotonic.set(self.monotonic.get() + by);
+        }
+
+        /// A suspend, or the clock being set ahead.
+        pub fn skip(&self, by: Duration) {
+            self.now.set(self.now.get() + by);
+        }
+
+        /// The clock being set back.
+        pub fn rewind(&self, by: Duration) {
+            self.now.set(self.now.get() - by);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> SystemTime {
+            self.now.get()
+        }
+
+        fn monotonic(&self) -> Duration {
+            self.monotonic.get()
+        }
+    }
+
+    const SEC: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn notices_jumps() {
+        let clock = FakeClock::new(at("2023-07-14T12:00:00Z"));
+        let mut jumps = JumpDetector::default();
+        assert_eq!(jumps.check(&clock), None, "nothing to compare against yet");
+
+        clock.tick(60 * SEC);
+        assert_eq!(jumps.check(&clock), None);
+
+        clock.tick(SEC);
+        clock.skip(8 * 3600 * SEC);
+        assert_eq!(jumps.check(&clock), Some(Jump::Forward(8 * 3600 * SEC)));
+        assert_eq!(jumps.check(&clock), None, "only reported once");
+
+        clock.tick(SEC);
+        clock.rewind(3600 * SEC);
+        assert_eq!(jumps.check(&clock), Some(Jump::Backward(3600 * SEC)));
+
+        // Set back to before the last look.
+        clock.tick(10 * SEC);
+        clock.rewind(15 * SEC);
+        assert_eq!(jumps.check(&clock), Some(Jump::Backward(15 * SEC)));
+    }
+
+    #[test]
+    fn tolerates_a_slow_loop() {
+        let clock = FakeClock::new(at("2023-07-14T12:00:00Z"));
+        let mut jumps = JumpDetector::default();
+        jumps.check(&clock);
+
+        clock.skip(2 * SEC);
+        assert_eq!(jumps.check(&clock), None);
+        clock.rewind(2 * SEC);
+        assert_eq!(jumps.check(&clock), None);
+        clock.skip(2 * SEC + Duration::from_millis(1));
+        assert!(jumps.check(&clock).is_some());
+        clock.rewind(2 * SEC + Duration::from_millis(1));
+        assert!(jumps.check(&clock).is_some());
+    }
+
+    #[test]
+    fn parses_gdbus_monitor() {
+        // Recorded with `gdbus monitor --system --dest org.freedesktop.login1
+        // --object-path /org/freedesktop/login1` across a suspend.
+        let recorded = "\
+Monitoring signals on object /org/freedesktop/login1 owned by org.freedesktop.login1
+The name org.freedesktop.login1 is owned by :1.4
+/org/freedesktop/login1: org.freedesktop.login1.Manager.PrepareForSleep (true,)
+/org/freedesktop/login1: org.freedesktop.DBus.Properties.PropertiesChanged ('org.freedesktop.login1.Manager', {'PreparingForSleep': <true>}, @as [])
+/org/freedesktop/login1: org.freedesktop.login1.Manager.PrepareForSleep (false,)
+/org/freedesktop/login1: org.freedesktop.login1.Manager.SessionRemoved ('c2', objectpath '/org/freedesktop/login1/session/c2')";
+        let events = recorded
+            .lines()
+            .filter_map(Sleep::parse)
+            .collect::<Vec<_>>();
+        assert_eq!(events, [Sleep::Suspending, Sleep::Resumed]);
+
+        assert_eq!(Sleep::parse("…PrepareForSleep (maybe,)"), None);
+    }
+
+    #[test]
+    fn forgets_wall_clock_plans() {
+        crate::timespec::tests::in_berlin();
+        let now = at("2023-07-14T12:00:00+02:00");
+        let mut dat = SharedAppData::new();
+        dat.triggers = vec![Trigger {
+            next: Some(now),
+            ..Trigger::default()
+        }];
+
+        let mut recurring = crate::ScheduleUi::default();
+        recurring.recurrence.enabled = true;
+        recurring.recurrence.weekdays = [true; 7];
+        // Armed this morning for tonight.
+        recurring.recurrence.time.0 = "22:00".into();
+        recurring.send = Some(at("2023-07-14T06:00:00+02:00"));
+        let mut begun = recurring.clone();
+        // Armed last night for this morning, so it's running.
+        begun.recurrence.time.0 = "07:00".into();
+        begun.send = Some(at("2023-07-13T23:00:00+02:00"));
+        let one_off = crate::ScheduleUi {
+            send: Some(at("2023-07-14T06:00:00+02:00")),
+            ..Default::default()
+        };
+        dat.schedules = vec![recurring, begun, one_off];
+
+        dat.forget_wall_clock(now);
+        assert_eq!(dat.triggers[0].next, None);
+        let sends = dat.schedules.iter().map(|s| s.send.is_some());
+        assert_eq!(sends.collect::<Vec<_>>(), [false, true, true]);
+    }
+}
@@ -9,10 +9,16 @@ use std::{
     time::{Duration, Instant},
 };
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use app_dirs2::{AppDataType, AppInfo};
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
 
-use crate::{Controller, ScheduleUi, SharedAppData, Strip};
+use crate::{
+    controller::Manual, easing::Easing, legacy, Action, Animation, AnimationKey, Calendar,
+    CalendarEdge, CalendarRule, Idle, IdleAction, IdleSource, Keyframe, Playback, Recurrence,
+    RelayAction, ScheduleUi, SharedAppData, Strip, Trigger,
+};
 
 impl SharedAppData {
     pub fn new() -> Self {
@@ -21,46 +27,271 @@ impl SharedAppData {
             // Yes, the program just started, so the strips *have* changed from
             // their previous, unknown state.
             strips_changed: true,
-            controller: Controller::Manual,
+            caps: Strip(u16::MAX, u16::MAX),
+            controller: Box::new(Manual),
             relay_enabled: false,
             relay_changed: false,
-            schedule: ScheduleUi {
-                start: ("6h30m".to_string(), None),
-                length: ("30m".to_string(), None),
-                endpoint: vec![Strip(u16::MAX, 0); 2],
-                send: None,
-                status_changed: false,
-                swap_on_stop: false,
-            },
+            schedules: vec![ScheduleUi::new("Wake up".to_string())],
+            triggers: vec![],
+            calendars: vec![],
+            idle: Idle::new(),
+            animations: vec![],
+            scenes: vec![],
+            default_scene: None,
+            preview: None,
+            notifications: Default::default(),
+            fade: None,
         }
     }
 
-    pub fn state_path() -> Result<PathBuf> {
+    pub fn config_dir() -> Result<PathBuf> {
         let info = AppInfo {
             name: "ledc",
             author: "ckie",
         };
-        Ok(app_dirs2::app_root(AppDataType::UserConfig, &info)?.join("state"))
+        Ok(app_dirs2::app_root(AppDataType::UserConfig, &info)?)
+    }
+
+    pub fn config_path() -> Result<PathBuf> {
+        Ok(Self::config_dir()?.join("config.ron"))
+    }
+
+    /// Where the config was kept, as bincode, before [`SharedAppData::config_path`].
+    pub fn state_path() -> Result<PathBuf> {
+        Ok(Self::config_dir()?.join("state"))
     }
 
+    /// Reads [`SharedAppData::config_path`], or failing that migrates the old bincode state
+    /// file, or failing that starts from the defaults.
     pub fn load_config() -> Result<Self> {
-        let path = Self::state_path()?;
-        if !path.try_exists()? {
-            let dat = Self::new();
-            dat.save_config()?;
+        if let Some(dat) = Self::read_config()? {
             return Ok(dat);
         }
+        let dat = Self::migrate().unwrap_or_else(|err| {
+            eprintln!("starting from the defaults: {err:#}");
+            Self::new()
+        });
+        dat.save_config()?;
+        Ok(dat)
+    }
+
+    /// Reads [`SharedAppData::config_path`] if it's there, without writing anything.
+    pub fn read_config() -> Result<Option<Self>> {
+        let path = Self::config_path()?;
+        if !path.try_exists()? {
+            return Ok(None);
+        }
 
-        Ok(bincode::deserialize(&fs::read(path)?)?)
+        let text = fs::read_to_string(&path)?;
+        let context = || format!("reading {}", path.display());
+        let Version { version } = ron::from_str(&text).with_context(context)?;
+        if version > CONFIG_VERSION {
+            bail!(
+                "{} is from a newer ledc (version {version}, this one knows up to {CONFIG_VERSION})",
+                path.display()
+            );
+        }
+        // Nothing to migrate from yet, besides the bincode state file.
+        let config: ConfigFile<Self> = ron::from_str(&text).with_context(context)?;
+        Ok(Some(config.state))
     }
 
-    pub fn save_config(&self) -> Result<()> {
+    /// Reads the bincode state file older versions kept. It's left where it is, but once
+    /// there's a config it's not looked at again.
+    fn migrate() -> Result<Self> {
         let path = Self::state_path()?;
-        fs::write(path, bincode::serialize(self)?)?;
+        if !path.try_exists()? {
+            bail!("no config yet");
+        }
+        let old: legacy::SharedAppData = bincode::deserialize(&fs::read(&path)?)
+            .with_context(|| format!("couldn't migrate {}", path.display()))?;
+        eprintln!(
+            "migrated {} to {}",
+            path.display(),
+            Self::config_path()?.display()
+        );
+        Ok(old.into())
+    }
+
+    pub fn save_config(&self) -> Result<()> {
+        let path = Self::config_path()?;
+        let config = ConfigFile {
+            version: CONFIG_VERSION,
+            state: self,
+        };
+        let text = ron::ser::to_string_pretty(&config, PrettyConfig::new())?;
+        // Written next to it and moved over, so a crash can't leave half a config.
+        let temp = path.with_extension("ron.new");
+        fs::write(&temp, format!("{HEADER}{text}\n"))?;
+        fs::rename(temp, path)?;
         Ok(())
     }
 }
 
+/// Goes up whenever old configs need migrating to be read.
+const CONFIG_VERSION: u32 = 1;
+
+const HEADER: &str = "\
+// ledc's config. It's written over whenever something changes in the app, so edit it
+// while ledc isn't running. Anything left out gets its default, see the README.
+";
+
+/// What's in [`SharedAppData::config_path`].
+#[derive(Serialize, Deserialize)]
+struct ConfigFile<T> {
+    version: u32,
+    state: T,
+}
+
+/// Just the version, to know how to read the rest.
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+impl ScheduleUi {
+    pub fn new(name: String) -> Self {
+        ScheduleUi {
+            name,
+            enabled: true,
+            priority: 0,
+            start: ("6h30m".to_string(), None),
+            keyframes: vec![Keyframe::default()],
+            recurrence: Recurrence::default(),
+            send: None,
+            segment: 0,
+            status_changed: false,
+            swap_on_stop: false,
+            range_error: None,
+        }
+    }
+}
+
+impl Default for ScheduleUi {
+    fn default() -> Self {
+        Self::new("Schedule".to_string())
+    }
+}
+
+impl Default for Keyframe {
+    fn default() -> Self {
+        Keyframe {
+            offset: ("0s".to_string(), None),
+            duration: ("30m".to_string(), None),
+            target: vec![Strip(u16::MAX, 0); 2],
+            easing: Easing::Linear,
+        }
+    }
+}
+
+impl Default for Recurrence {
+    fn default() -> Self {
+        Recurrence {
+            enabled: false,
+            time: ("06:30".to_string(), None),
+            weekdays: [true, true, true, true, true, false, false],
+            skip_next: false,
+        }
+    }
+}
+
+impl Trigger {
+    pub fn new(name: String) -> Self {
+        Trigger {
+            name,
+            enabled: false,
+            cron: ("0 0 9,13 * * Mon-Fri".to_string(), None),
+            timezone: (String::new(), None),
+            action: Action::Relay(RelayAction::On),
+            next: None,
+        }
+    }
+}
+
+impl Default for Trigger {
+    fn default() -> Self {
+        Self::new("Trigger".to_string())
+    }
+}
+
+impl Calendar {
+    pub fn new() -> Self {
+        Calendar {
+            path: String::new(),
+            enabled: false,
+            rules: vec![CalendarRule::new()],
+            status: String::new(),
+            upcoming: vec![],
+        }
+    }
+}
+
+impl Default for Calendar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CalendarRule {
+    pub fn new() -> Self {
+        CalendarRule {
+            matching: String::new(),
+            edge: CalendarEdge::Start,
+            lead: ("10m".to_string(), None),
+            action: Action::Relay(RelayAction::On),
+        }
+    }
+}
+
+impl Default for CalendarRule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Idle {
+    pub fn new() -> Self {
+        Idle {
+            enabled: false,
+            source: IdleSource::Auto,
+            timeout: ("10m".to_string(), None),
+            action: IdleAction::Dim(0.2),
+            status: String::new(),
+            away: false,
+            relay_before: None,
+        }
+    }
+}
+
+impl Default for Idle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Animation {
+    /// Ten looping seconds, starting out at `strips`.
+    pub fn new(name: String, strips: &[Strip]) -> Self {
+        let key = |value| {
+            vec![AnimationKey {
+                at: 0.0,
+                value,
+                easing: Easing::Linear,
+            }]
+        };
+        Animation {
+            name,
+            length: 10.0,
+            playback: Playback::Loop,
+            tracks: strips
+                .iter()
+                .map(|strip| [key(strip.0), key(strip.1)])
+                .collect(),
+            started_at: None,
+        }
+    }
+}
+
 impl Default for SharedAppData {
     fn default() -> Self {
         Self::new()
@@ -85,11 +316,32 @@ pub fn config_thread(arc: Arc<Mutex<SharedAppData>>, flag: Arc<AtomicBool>) {
         }
 
         let config = arc.lock().unwrap();
-        if Some(config.clone()) != last_config {
-            config.save_config().unwrap();
+        if Some(&*config) != last_config.as_ref() {
+            match config.save_config() {
+                Ok(()) => last_config = Some(config.clone()),
+                // Left as it was, so the next change tries again.
+                Err(err) => eprintln!("couldn't save the config: {err:#}"),
+            }
         }
 
-        last_config = Some(config.clone());
         next_save = Instant::now() + debounce_dur;
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use std::{env, path::PathBuf, sync::Once};
+
+    use super::*;
+
+    /// Points the config directory at one of the tests' own, so they never touch the real
+    /// one, and returns it.
+    pub fn scratch_config() -> PathBuf {
+        static XDG: Once = Once::new();
+        XDG.call_once(|| {
+            let dir = env::temp_dir().join(format!("ledc-config-{}", std::process::id()));
+            env::set_var("XDG_CONFIG_HOME", dir);
+        });
+        SharedAppData::config_dir().unwrap()
+    }
+}
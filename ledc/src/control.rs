@@ -0,0 +1,118 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{focus::Focus, scene, Notification, SharedAppData};
+
+/// How long a client gets to send its command.
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where the running app listens for commands, one line each, answering with a line that
+/// starts with `ok` or `error`.
+pub fn socket_path() -> Result<PathBuf> {
+    Ok(SharedAppData::config_dir()?.join("control.sock"))
+}
+
+/// Does what `line` says, answering with what became of it.
+pub fn handle(dat: &mut SharedAppData, line: &str, now: SystemTime) -> Result<String> {
+    let words = line.split_whitespace().collect::<Vec<_>>();
+    match words.as_slice() {
+        ["focus", args @ ..] => {
+            if dat.controller.name() != "Focus" {
+                if args != ["start"] {
+                    bail!("the focus timer isn't running, try focus start");
+                }
+                dat.controller = Box::<Focus>::default();
+            }
+            dat.controller.command(args, now)
+        }
+        ["notify", args @ ..] => {
+            dat.notify(Notification::parse(args)?, now)?;
+            Ok(match dat.notifications.queue.len() {
+                0 | 1 => "queued".into(),
+                n => format!("queued behind {}", n - 1),
+            })
+        }
+        ["scene", args @ ..] => scene::command(dat, args),
+        _ => bail!("unknown command {line:?}"),
+    }
+}
+
+/// Answers commands on [`socket_path`] from a background thread.
+pub fn serve(arc: Arc<Mutex<SharedAppData>>) {
+    let listener = socket_path().and_then(|path| {
+        if path.exists() {
+            if UnixStream::connect(&path).is_ok() {
+                bail!("another ledc already has {}", path.display());
+            }
+            // Left over from a crash.
+            fs::remove_file(&path)?;
+        }
+        Ok(UnixListener::bind(path)?)
+    });
+    let listener = match listener {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("not listening for commands: {err:#}");
+            return;
+        }
+    };
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            // One client that never finishes its line mustn't hold up everyone after it.
+            if stream.set_read_timeout(Some(READ_TIMEOUT)).is_err() {
+                continue;
+            }
+            let mut line = String::new();
+            if BufReader::new(&stream).read_line(&mut line).is_err() {
+                continue;
+            }
+            let reply = {
+                let mut dat = arc.lock().unwrap();
+                handle(&mut dat, line.trim(), SystemTime::now())
+            };
+            let reply = match reply {
+                Ok(reply) => format!("ok {reply}\n"),
+                Err(err) => format!("error {err:#}\n"),
+            };
+            stream.write_all(reply.as_bytes()).ok();
+        }
+    });
+}
+
+/// Sends `line` to the running app. Without one, it's done to the config instead (if
+/// `offline` allows it), for whenever it next starts.
+pub fn request(line: &str, offline: bool) -> Result<String> {
+    let Ok(mut stream) = socket_path().and_then(|path| Ok(UnixStream::connect(path)?)) else {
+        if !offline {
+            bail!("ledc isn't running");
+        }
+        let mut dat = SharedAppData::load_config()?;
+        let reply = handle(&mut dat, line, SystemTime::now())?;
+        dat.save_config()?;
+        return Ok(reply);
+    };
+    stream.write_all(format!("{line}\n").as_bytes())?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    let reply = reply.trim_end();
+    if let Some(reply) = reply.strip_prefix("ok ") {
+        Ok(reply.to_string())
+    } else if let Some(err) = reply.strip_prefix("error ") {
+        Err(anyhow!("{err}"))
+    } else {
+        bail!("unexpected reply {reply:?}")
+    }
+}
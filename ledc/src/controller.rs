@@ -0,0 +1,258 @@
+use std::{fmt, time::SystemTime};
+
+use anyhow::Result;
+use eframe::egui::Ui;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::{
+    animation::Player,
+    audio::Audio,
+    focus::Focus,
+    script::Script,
+    solar::Solar,
+    wave::{Flicker, Wave},
+    Strip,
+};
+
+/// Decides what the strips look like whenever no schedule has them.
+///
+/// New effects implement this and get a line in [`REGISTRY`], the update loop and the UI
+/// only ever go through the trait.
+pub trait Controller: Send {
+    /// What it's registered as, see [`REGISTRY`].
+    fn name(&self) -> &'static str;
+
+    /// What the strips should look like at `at`, given what they look like now and whether
+    /// the relay's on. `None` leaves them be.
+    fn output(&mut self, at: SystemTime, strips: &[Strip], relay: bool) -> Option<Vec<Strip>>;
+
+    /// Whether the output keeps changing, so it has to be streamed (and redrawn) all the time.
+    fn realtime(&self) -> bool {
+        false
+    }
+
+    /// Whether the output follows something outside of ledc, like sound. Simulations can't
+    /// know what that'll be, so they leave these out.
+    fn live(&self) -> bool {
+        false
+    }
+
+    /// Tells apart the ones registered under the same name, like different scripts.
+    fn label(&self) -> String {
+        self.name().to_string()
+    }
+
+    /// The settings it's running with, as name and value.
+    fn params(&self) -> Vec<(&'static str, String)>;
+
+    /// Draws its settings.
+    fn ui(&mut self, _ui: &mut Ui) {}
+
+    /// The user just set the strips by hand, to `strips`.
+    fn strips_touched(&mut self, _strips: &[Strip]) {}
+
+    /// Does what `args` say, from `ledc` on the command line. Answers with how it went.
+    fn command(&mut self, _args: &[&str], _now: SystemTime) -> Result<String> {
+        anyhow::bail!("{} doesn't take commands", self.name())
+    }
+
+    /// Its state for the config, read back by its [`Registration::load`].
+    fn save(&self) -> Value;
+
+    fn clone_box(&self) -> Box<dyn Controller>;
+}
+
+pub struct Registration {
+    pub name: &'static str,
+    pub new: fn() -> Box<dyn Controller>,
+    pub load: fn(Value) -> Result<Box<dyn Controller>>,
+}
+
+/// Every control mode, in the order the UI offers them.
+pub static REGISTRY: &[Registration] = &[
+    register::<Manual>("Manual"),
+    register::<Wave>("Wave"),
+    register::<Flicker>("Flicker"),
+    register::<Solar>("Solar"),
+    register::<Audio>("Audio"),
+    register::<Script>("Script"),
+    register::<Focus>("Focus"),
+    register::<Player>("Animation"),
+];
+
+const fn register<T>(name: &'static str) -> Registration
+where
+    T: Controller + Default + DeserializeOwned + 'static,
+{
+    fn new<T: Controller + Default + 'static>() -> Box<dyn Controller> {
+        Box::<T>::default()
+    }
+    fn load<T: Controller + DeserializeOwned + 'static>(
+        state: Value,
+    ) -> Result<Box<dyn Controller>> {
+        Ok(Box::new(serde_json::from_value::<T>(state)?))
+    }
+
+    Registration {
+        name,
+        new: new::<T>,
+        load: load::<T>,
+    }
+}
+
+pub fn lookup(name: &str) -> Option<&'static Registration> {
+    REGISTRY
+        .iter()
+        .find(|registration| registration.name == name)
+}
+
+/// Leaves the strips to the user.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Manual;
+
+impl Controller for Manual {
+    fn name(&self) -> &'static str {
+        "Manual"
+    }
+
+    fn output(&mut self, _at: SystemTime, _strips: &[Strip], _relay: bool) -> Option<Vec<Strip>> {
+        None
+    }
+
+    fn params(&self) -> Vec<(&'static str, String)> {
+        vec![]
+    }
+
+    fn save(&self) -> Value {
+        Value::Null
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+}
+
+/// How a controller's stored in the config: the registered name and whatever state it saved.
+#[derive(Serialize, Deserialize)]
+struct Saved {
+    name: String,
+    /// Left out, it starts from its defaults.
+    #[serde(default)]
+    state: Value,
+}
+
+impl Serialize for Box<dyn Controller> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Saved {
+            name: self.name().to_string(),
+            state: self.save(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Box<dyn Controller> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let Saved { name, state } = Saved::deserialize(deserializer)?;
+        let registration = lookup(&name)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown controller {name:?}")))?;
+        if state.is_null() {
+            return Ok((registration.new)());
+        }
+        (registration.load)(state).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Clone for Box<dyn Controller> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+impl PartialEq for dyn Controller {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name() && self.save() == other.save()
+    }
+}
+
+// derive(PartialEq) on structs holding a Box<dyn Controller> needs this one, see
+// https://github.com/rust-lang/rust/issues/31740
+impl PartialEq<&Self> for Box<dyn Controller> {
+    fn eq(&self, other: &&Self) -> bool {
+        **self == ***other
+    }
+}
+
+impl fmt::Debug for dyn Controller {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct(self.name());
+        for (name, value) in self.params() {
+            debug.field(name, &value);
+        }
+        debug.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::Script;
+
+    fn through_ron(controller: &dyn Controller) -> Box<dyn Controller> {
+        ron::from_str(&ron::to_string(&controller.clone_box()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn every_controller_round_trips() {
+        for registration in REGISTRY {
+            let controller = (registration.new)();
+            assert_eq!(controller.name(), registration.name);
+            let loaded = (registration.load)(controller.save()).unwrap();
+            assert_eq!(&loaded, &controller, "{}", registration.name);
+            assert_eq!(&through_ron(&*controller), &controller);
+        }
+
+        // Settings that aren't the defaults too.
+        let mut wave = Wave::default();
+        wave.base[1] = Strip(1, 2);
+        wave.channels[0][1].phase = 0.25;
+        let mut script = Script::new("lava".into());
+        script.params.insert("speed".into(), 3.0);
+        for controller in [Box::new(wave) as Box<dyn Controller>, Box::new(script)] {
+            assert_ne!(
+                &controller,
+                &lookup(controller.name()).map(|r| (r.new)()).unwrap()
+            );
+            assert_eq!(&through_ron(&*controller), &controller);
+        }
+    }
+
+    #[test]
+    fn missing_state_starts_from_the_defaults() {
+        for text in [r#"(name: "Flicker")"#, r#"(name: "Flicker", state: ())"#] {
+            let controller: Box<dyn Controller> = ron::from_str(text).unwrap();
+            assert_eq!(&controller, &Flicker::default().clone_box(), "{text}");
+        }
+        // Fields left out of the state get theirs.
+        let controller: Box<dyn Controller> =
+            ron::from_str(r#"(name: "Flicker", state: {"speed": 2.0})"#).unwrap();
+        let want = Flicker {
+            speed: 2.0,
+            ..Flicker::default()
+        };
+        assert_eq!(&controller, &want.clone_box());
+    }
+
+    #[test]
+    fn unknown_controllers_dont_load() {
+        let err = ron::from_str::<Box<dyn Controller>>(r#"(name: "Disco")"#).unwrap_err();
+        assert!(
+            err.to_string().contains(r#"unknown controller "Disco""#),
+            "{err}"
+        );
+        assert!(lookup("Disco").is_none());
+        // Nor does state of the wrong shape.
+        assert!(ron::from_str::<Box<dyn Controller>>(r#"(name: "Wave", state: 5)"#).is_err());
+    }
+}
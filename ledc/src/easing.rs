@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+
+/// How a scheduled transition moves from its start state to its endpoint.
+///
+/// Only `Linear` can be handed to the MCU as-is, the rest get streamed from the host.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Default)]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseInOut,
+    Sigmoid,
+    /// Linear in CIE L* (perceived lightness) instead of in PWM duty.
+    Perceptual,
+}
+
+impl Easing {
+    pub const ALL: [Easing; 4] = [
+        Easing::Linear,
+        Easing::EaseInOut,
+        Easing::Sigmoid,
+        Easing::Perceptual,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Easing::Linear => "linear",
+            Easing::EaseInOut => "ease in/out",
+            Easing::Sigmoid => "sigmoid",
+            Easing::Perceptual => "perceptual",
+        }
+    }
+
+    /// Whether the MCU's own lerp (IInterpolateFrame) can render this.
+    pub fn device_native(self) -> bool {
+        self == Easing::Linear
+    }
+
+    /// Maps linear progress in `0..=1` onto the eased progress.
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear | Easing::Perceptual => t,
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+            Easing::Sigmoid => {
+                let k = 10.0;
+                let s = |x: f32| 1.0 / (1.0 + (-k * (x - 0.5)).exp());
+                (s(t) - s(0.0)) / (s(1.0) - s(0.0))
+            }
+        }
+    }
+
+    /// Interpolates one channel `t` of the way from `from` to `to`.
+    pub fn interpolate(self, from: u16, to: u16, t: f32) -> u16 {
+        let t = self.apply(t);
+        let (from, to) = (f32::from(from), f32::from(to));
+        let val = if self == Easing::Perceptual {
+            let max = f32::from(u16::MAX);
+            let (from, to) = (lightness(from / max), lightness(to / max));
+            luminance(from + (to - from) * t) * max
+        } else {
+            from + (to - from) * t
+        };
+        val.round().clamp(0.0, f32::from(u16::MAX)) as u16
+    }
+}
+
+/// CIE 1976 L* from relative luminance, both normalised to `0..=1`.
+fn lightness(y: f32) -> f32 {
+    if y <= 216.0 / 24389.0 {
+        y * 24389.0 / 27.0 / 100.0
+    } else {
+        (116.0 * y.cbrt() - 16.0) / 100.0
+    }
+}
+
+fn luminance(l: f32) -> f32 {
+    let l = l * 100.0;
+    if l <= 8.0 {
+        l * 27.0 / 24389.0
+    } else {
+        ((l + 16.0) / 116.0).powi(3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curves_start_and_end_in_place() {
+        for easing in Easing::ALL {
+            assert_eq!(easing.apply(0.0), 0.0, "{}", easing.name());
+            assert!((easing.apply(1.0) - 1.0).abs() < 1e-6, "{}", easing.name());
+            assert_eq!(
+                easing.interpolate(100, 60000, 0.0),
+                100,
+                "{}",
+                easing.name()
+            );
+            assert_eq!(
+                easing.interpolate(100, 60000, 1.0),
+                60000,
+                "{}",
+                easing.name()
+            );
+        }
+    }
+
+    #[test]
+    fn progress_is_clamped() {
+        for easing in Easing::ALL {
+            assert_eq!(easing.apply(-1.0), easing.apply(0.0));
+            assert_eq!(easing.apply(2.0), easing.apply(1.0));
+        }
+    }
+
+    #[test]
+    fn curves_only_go_forwards() {
+        for easing in Easing::ALL {
+            let mut last = 0;
+            for step in 0..=100 {
+                let val = easing.interpolate(0, u16::MAX, step as f32 / 100.0);
+                assert!(val >= last, "{} went back at {step}%", easing.name());
+                last = val;
+            }
+        }
+    }
+
+    #[test]
+    fn s_curves_are_symmetric() {
+        for easing in [Easing::EaseInOut, Easing::Sigmoid] {
+            assert!((easing.apply(0.5) - 0.5).abs() < 1e-6);
+            for t in [0.1, 0.25, 0.4] {
+                let (a, b) = (easing.apply(t), 1.0 - easing.apply(1.0 - t));
+                assert!((a - b).abs() < 1e-6, "{} at {t}", easing.name());
+                assert!(a < t, "{} should start slow", easing.name());
+            }
+        }
+    }
+
+    #[test]
+    fn perceptual_spends_longer_in_the_dark() {
+        // Halfway in lightness is well under halfway in duty.
+        let mid = Easing::Perceptual.interpolate(0, u16::MAX, 0.5);
+        let linear = Easing::Linear.interpolate(0, u16::MAX, 0.5);
+        assert!(mid < linear / 2, "{mid}");
+        // And it's the same whichever way it's going.
+        let back = Easing::Perceptual.interpolate(u16::MAX, 0, 0.5);
+        assert!(mid.abs_diff(back) <= 1);
+    }
+
+    #[test]
+    fn lightness_round_trips() {
+        for y in [0.0, 0.001, 216.0 / 24389.0, 0.2, 0.5, 1.0] {
+            assert!((luminance(lightness(y)) - y).abs() < 1e-5, "{y}");
+        }
+    }
+}
@@ -0,0 +1,466 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Result};
+use eframe::egui::{DragValue, Ui};
+use serde::{Deserialize, Serialize};
+
+use crate::{control, controller::Controller, ui, Strip, WaveType};
+
+/// How long one pulse of the cue takes.
+const CUE_PERIOD: Duration = Duration::from_secs(2);
+/// How far the cue dims the lights at the bottom of each pulse.
+const CUE_DEPTH: f64 = 0.3;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Phase {
+    Work,
+    ShortBreak,
+    LongBreak,
+}
+
+impl Phase {
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Work => "work",
+            Phase::ShortBreak => "short break",
+            Phase::LongBreak => "long break",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum Timer {
+    Stopped,
+    Running { ends_at: SystemTime },
+    Paused { left: Duration },
+}
+
+/// A pomodoro timer, lighting each phase its own way.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct Focus {
+    pub work_minutes: f32,
+    pub short_break_minutes: f32,
+    pub long_break_minutes: f32,
+    /// Every this many work phases, the break is a long one.
+    pub long_break_every: u32,
+    pub work: Vec<Strip>,
+    pub short_break: Vec<Strip>,
+    pub long_break: Vec<Strip>,
+    /// How long before a phase ends the lights start pulsing.
+    pub cue_seconds: f32,
+    pub phase: Phase,
+    /// Work phases done since the last long break.
+    pub done: u32,
+    /// Kept in wall-clock time, so it carries on across restarts.
+    pub timer: Timer,
+    /// The `at` of the last output or command, what the rest goes by. That way a simulation
+    /// sees its own time, not ours.
+    #[serde(skip)]
+    seen_at: Option<SystemTime>,
+}
+
+impl Default for Focus {
+    fn default() -> Self {
+        Focus {
+            work_minutes: 25.0,
+            short_break_minutes: 5.0,
+            long_break_minutes: 15.0,
+            long_break_every: 4,
+            work: vec![Strip(u16::MAX, u16::MAX / 2); 2],
+            short_break: vec![Strip(0, u16::MAX / 4); 2],
+            long_break: vec![Strip(0, u16::MAX / 8); 2],
+            cue_seconds: 30.0,
+            phase: Phase::Work,
+            done: 0,
+            timer: Timer::Stopped,
+            seen_at: None,
+        }
+    }
+}
+
+impl Focus {
+    fn length(&self, phase: Phase) -> Duration {
+        let minutes = match phase {
+            Phase::Work => self.work_minutes,
+            Phase::ShortBreak => self.short_break_minutes,
+            Phase::LongBreak => self.long_break_minutes,
+        };
+        // Zero-length phases would never let it catch up.
+        Duration::from_secs_f32(minutes.max(0.1) * 60.0)
+    }
+
+    fn target(&self, phase: Phase) -> &[Strip] {
+        match phase {
+            Phase::Work => &self.work,
+            Phase::ShortBreak => &self.short_break,
+            Phase::LongBreak => &self.long_break,
+        }
+    }
+
+    /// On to the next phase, leaving the timer be.
+    fn advance(&mut self) {
+        self.phase = match self.phase {
+            Phase::Work => {
+                self.done += 1;
+                if self.done >= self.long_break_every {
+                    self.done = 0;
+                    Phase::LongBreak
+                } else {
+                    Phase::ShortBreak
+                }
+            }
+            Phase::ShortBreak | Phase::LongBreak => Phase::Work,
+        };
+    }
+
+    /// Goes through whatever phases ended by `now`, like while we weren't running.
+    fn catch_up(&mut self, now: SystemTime) {
+        while let Timer::Running { ends_at } = self.timer {
+            if now < ends_at {
+                break;
+            }
+            self.advance();
+            self.timer = Timer::Running {
+                ends_at: ends_at + self.length(self.phase),
+            };
+        }
+    }
+
+    /// Only before the first output is there no `at` to go by.
+    fn now(&self) -> SystemTime {
+        self.seen_at.unwrap_or_else(SystemTime::now)
+    }
+
+    fn left(&self, now: SystemTime) -> Option<Duration> {
+        match self.timer {
+            Timer::Stopped => None,
+            Timer::Running { ends_at } => Some(ends_at.duration_since(now).unwrap_or_default()),
+            Timer::Paused { left } => Some(left),
+        }
+    }
+
+    fn cueing(&self, now: SystemTime) -> bool {
+        matches!(self.timer, Timer::Running { .. })
+            && self
+                .left(now)
+                .is_some_and(|left| left.as_secs_f32() <= self.cue_seconds)
+    }
+
+    pub fn status(&self, now: SystemTime) -> String {
+        let Some(left) = self.left(now) else {
+            return "stopped".into();
+        };
+        let secs = left.as_secs();
+        format!(
+            "{}{}, {}:{:02} left, {} of {} done",
+            if matches!(self.timer, Timer::Paused { .. }) {
+                "paused in "
+            } else {
+                ""
+            },
+            self.phase.name(),
+            secs / 60,
+            secs % 60,
+            self.done,
+            self.long_break_every
+        )
+    }
+
+    pub fn start(&mut self, now: SystemTime) {
+        self.timer = match self.timer {
+            Timer::Stopped => {
+                self.phase = Phase::Work;
+                self.done = 0;
+                Timer::Running {
+                    ends_at: now + self.length(Phase::Work),
+                }
+            }
+            Timer::Paused { left } => Timer::Running {
+                ends_at: now + left,
+            },
+            running @ Timer::Running { .. } => running,
+        };
+    }
+
+    pub fn pause(&mut self, now: SystemTime) -> Result<()> {
+        let Timer::Running { .. } = self.timer else {
+            bail!("not running");
+        };
+        self.timer = Timer::Paused {
+            left: self.left(now).unwrap_or_default(),
+        };
+        Ok(())
+    }
+
+    pub fn skip(&mut self, now: SystemTime) -> Result<()> {
+        if self.timer == Timer::Stopped {
+            bail!("not started");
+        }
+        self.advance();
+        let length = self.length(self.phase);
+        self.timer = match self.timer {
+            Timer::Paused { .. } => Timer::Paused { left: length },
+            _ => Timer::Running {
+                ends_at: now + length,
+            },
+        };
+        Ok(())
+    }
+}
+
+impl Controller for Focus {
+    fn name(&self) -> &'static str {
+        "Focus"
+    }
+
+    fn output(&mut self, at: SystemTime, _strips: &[Strip], _relay: bool) -> Option<Vec<Strip>> {
+        self.seen_at = Some(at);
+        self.catch_up(at);
+        if self.timer == Timer::Stopped {
+            return None;
+        }
+        let target = self.target(self.phase).to_vec();
+        if !self.cueing(at) {
+            return Some(target);
+        }
+
+        // A gentle breathing dip to say the phase is about to end.
+        let since_epoch = at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let pos = since_epoch.as_secs_f64() / CUE_PERIOD.as_secs_f64();
+        let level = 1.0 - CUE_DEPTH * WaveType::Breathe.shape(pos);
+        let dim = |value: u16| (f64::from(value) * level) as u16;
+        Some(
+            target
+                .iter()
+                .map(|strip| Strip(dim(strip.0), dim(strip.1)))
+                .collect(),
+        )
+    }
+
+    fn realtime(&self) -> bool {
+        self.seen_at.is_some_and(|at| self.cueing(at))
+    }
+
+    fn params(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("work_minutes", self.work_minutes.to_string()),
+            ("short_break_minutes", self.short_break_minutes.to_string()),
+            ("long_break_minutes", self.long_break_minutes.to_string()),
+            ("long_break_every", self.long_break_every.to_string()),
+            ("cue_seconds", self.cue_seconds.to_string()),
+            ("status", self.status(self.now())),
+        ]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.group(|ui| {
+            ui.label("Focus timer");
+            let now = self.now();
+            ui.horizontal(|ui| {
+                ui.label(self.status(now));
+                if ui.button("Start").clicked() {
+                    self.start(now);
+                }
+                if ui.button("Pause").clicked() {
+                    self.pause(now).ok();
+                }
+                if ui.button("Skip").clicked() {
+                    self.skip(now).ok();
+                }
+                if ui.button("Stop").clicked() {
+                    self.timer = Timer::Stopped;
+                }
+            });
+
+            ui.horizontal(|ui| {
+                for (minutes, name) in [
+                    (&mut self.work_minutes, "work"),
+                    (&mut self.short_break_minutes, "short break"),
+                    (&mut self.long_break_minutes, "long break"),
+                ] {
+                    ui.add(
+                        DragValue::new(minutes)
+                            .clamp_range(0.1..=600.0)
+                            .suffix(" min"),
+                    );
+                    ui.label(name);
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("long break every");
+                ui.add(DragValue::new(&mut self.long_break_every).clamp_range(1..=20));
+                ui.label("work phases, pulse");
+                ui.add(
+                    DragValue::new(&mut self.cue_seconds)
+                        .clamp_range(0.0..=600.0)
+                        .suffix(" s"),
+                );
+                ui.label("before each ends");
+            });
+
+            ui.label("Work");
+            ui::strip_controls(ui, &mut self.work);
+            ui.label("Short break");
+            ui::strip_controls(ui, &mut self.short_break);
+            ui.label("Long break");
+            ui::strip_controls(ui, &mut self.long_break);
+        });
+    }
+
+    fn command(&mut self, args: &[&str], now: SystemTime) -> Result<String> {
+        self.seen_at = Some(now);
+        self.catch_up(now);
+        match args {
+            ["start"] => self.start(now),
+            ["pause"] => self.pause(now)?,
+            ["skip"] => self.skip(now)?,
+            ["stop"] => self.timer = Timer::Stopped,
+            ["status"] => {}
+            _ => bail!("usage: focus start|pause|skip|stop|status"),
+        }
+        Ok(self.status(now))
+    }
+
+    fn save(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+}
+
+/// `ledc focus start|pause|skip|stop|status`
+pub fn cli(args: &[String]) -> Result<()> {
+    println!(
+        "{}",
+        control::request(&format!("focus {}", args.join(" ")), true)?
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn started() -> (Focus, SystemTime) {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mut focus = Focus::default();
+        focus.command(&["start"], start).unwrap();
+        (focus, start)
+    }
+
+    #[test]
+    fn catches_up_after_a_restart() {
+        let (focus, start) = started();
+        // Saved with the config, and read back most of an hour later.
+        let mut focus: Focus = serde_json::from_value(focus.save()).unwrap();
+        let later = start + 57 * MINUTE;
+        let out = focus.output(later, &[], false).unwrap();
+
+        // Work 25, break 5, work 25, and 2 minutes into the second break.
+        assert_eq!(focus.phase, Phase::ShortBreak);
+        assert_eq!(focus.done, 2);
+        assert_eq!(
+            focus.timer,
+            Timer::Running {
+                ends_at: start + 60 * MINUTE
+            }
+        );
+        assert_eq!(out, focus.short_break);
+        assert_eq!(focus.status(later), "short break, 3:00 left, 2 of 4 done");
+    }
+
+    #[test]
+    fn long_break_every_few() {
+        let (mut focus, start) = started();
+        focus.long_break_every = 2;
+        let mut phases = vec![];
+        let mut now = start;
+        for _ in 0..6 {
+            let Timer::Running { ends_at } = focus.timer else {
+                panic!("{:?}", focus.timer);
+            };
+            now = ends_at;
+            focus.output(now, &[], false);
+            phases.push(focus.phase);
+        }
+        use Phase::*;
+        assert_eq!(
+            phases,
+            [ShortBreak, Work, LongBreak, Work, ShortBreak, Work]
+        );
+        // Work, short, work, long, work, short.
+        assert_eq!(now, start + (25 * 3 + 5 * 2 + 15) * MINUTE);
+    }
+
+    #[test]
+    fn pause_skip_and_stop() {
+        let (mut focus, start) = started();
+        let now = start + 10 * MINUTE;
+        assert_eq!(
+            focus.command(&["pause"], now).unwrap(),
+            "paused in work, 15:00 left, 0 of 4 done"
+        );
+        assert!(focus.command(&["pause"], now).is_err());
+        // Paused, the time doesn't run out.
+        let much_later = now + 60 * MINUTE;
+        focus.output(much_later, &[], false);
+        assert_eq!(focus.timer, Timer::Paused { left: 15 * MINUTE });
+
+        // Skipping while paused stays paused, with the whole next phase left.
+        focus.command(&["skip"], much_later).unwrap();
+        assert_eq!(focus.phase, Phase::ShortBreak);
+        assert_eq!(focus.timer, Timer::Paused { left: 5 * MINUTE });
+        focus.command(&["start"], much_later).unwrap();
+        assert_eq!(
+            focus.timer,
+            Timer::Running {
+                ends_at: much_later + 5 * MINUTE
+            }
+        );
+        focus.command(&["skip"], much_later).unwrap();
+        assert_eq!(focus.phase, Phase::Work);
+        assert_eq!(
+            focus.timer,
+            Timer::Running {
+                ends_at: much_later + 25 * MINUTE
+            }
+        );
+
+        assert_eq!(focus.command(&["stop"], much_later).unwrap(), "stopped");
+        assert_eq!(focus.output(much_later, &[], false), None);
+        assert!(focus.command(&["skip"], much_later).is_err());
+        assert!(focus.command(&["pause"], much_later).is_err());
+        // Starting over begins a fresh count.
+        focus.command(&["start"], much_later).unwrap();
+        assert_eq!((focus.phase, focus.done), (Phase::Work, 0));
+        assert!(focus.command(&["snooze"], much_later).is_err());
+    }
+
+    #[test]
+    fn goes_by_at_not_the_clock() {
+        // Well away from now, like a simulation would be.
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mut focus = Focus::default();
+        focus.command(&["start"], start).unwrap();
+        assert!(!focus.realtime());
+
+        // Pulsing for the last 30 s of the 25 min.
+        focus.output(start + Duration::from_secs(24 * 60 + 40), &[], false);
+        assert!(focus.realtime());
+        assert!(focus
+            .params()
+            .contains(&("status", "work, 0:20 left, 0 of 4 done".into())));
+
+        // Into the break, and steady again.
+        focus.output(start + Duration::from_secs(25 * 60 + 10), &[], false);
+        assert!(!focus.realtime());
+        assert_eq!(focus.phase, Phase::ShortBreak);
+    }
+}
@@ -0,0 +1,397 @@
+use std::{
+    env,
+    process::Command,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Result};
+use x11rb::{
+    connection::Connection, protocol::screensaver::ConnectionExt, rust_connection::RustConnection,
+};
+
+use crate::{IdleAction, IdleSource, SharedAppData};
+
+/// How often the idle time gets looked at. Also how long coming back takes to notice.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The X server's screensaver extension, which counts from the last key press or mouse move.
+///
+/// Uses `$DISPLAY`, so it works the same against Xvfb.
+pub struct X11 {
+    conn: RustConnection,
+    root: u32,
+}
+
+impl X11 {
+    pub fn connect() -> Result<Self> {
+        let (conn, screen) = x11rb::connect(None)?;
+        let root = conn.setup().roots[screen].root;
+        Ok(X11 { conn, root })
+    }
+
+    pub fn idle(&self) -> Result<Duration> {
+        let info = self.conn.screensaver_query_info(self.root)?.reply()?;
+        Ok(Duration::from_millis(info.ms_since_user_input.into()))
+    }
+}
+
+/// Asks logind how long this session's been idle, by way of `gdbus` like
+/// [`crate::clock::watch_logind`].
+///
+/// Honours `$DBUS_SYSTEM_BUS_ADDRESS`, so a stand-in bus works too.
+pub fn logind_idle() -> Result<Duration> {
+    let get = |property: &str| -> Result<String> {
+        let output = Command::new("gdbus")
+            .args([
+                "call",
+                "--system",
+                // It's asked again every CHECK_INTERVAL anyway.
+                "--timeout",
+                "1",
+                "--dest",
+                "org.freedesktop.login1",
+                "--object-path",
+                "/org/freedesktop/login1/session/auto",
+                "--method",
+                "org.freedesktop.DBus.Properties.Get",
+                "org.freedesktop.login1.Session",
+                property,
+            ])
+            .output()?;
+        if !output.status.success() {
+            bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(String::from_utf8(output.stdout)?)
+    };
+
+    // Like `(<true>,)`
+    if !get("IdleHint")?.contains("true") {
+        return Ok(Duration::ZERO);
+    }
+    // Like `(<uint64 1700000000000000>,)`, in microseconds of wall-clock time.
+    let since = get("IdleSinceHint")?;
+    let micros = since
+        .split(|c: char| !c.is_ascii_digit())
+        .rfind(|word| !word.is_empty())
+        .ok_or_else(|| anyhow!("couldn't read IdleSinceHint from {since:?}"))?
+        .parse::<u64>()?;
+    let since = UNIX_EPOCH + Duration::from_micros(micros);
+    Ok(SystemTime::now().duration_since(since).unwrap_or_default())
+}
+
+/// Somewhere to ask how long the user's been away.
+pub trait Probe: Send + 'static {
+    fn idle(&mut self, source: IdleSource) -> Result<Duration>;
+}
+
+/// The real thing, X11 or logind.
+#[derive(Default)]
+pub struct SystemProbe {
+    x11: Option<X11>,
+}
+
+impl Probe for SystemProbe {
+    fn idle(&mut self, source: IdleSource) -> Result<Duration> {
+        let x11 = match source {
+            IdleSource::Logind => false,
+            IdleSource::X11 => true,
+            IdleSource::Auto => env::var_os("DISPLAY").is_some(),
+        };
+        if !x11 {
+            return logind_idle();
+        }
+        let x11 = match &self.x11 {
+            Some(x11) => x11,
+            None => self.x11.insert(X11::connect()?),
+        };
+        let idle = x11.idle();
+        if idle.is_err() {
+            // The server went away, try again next time.
+            self.x11 = None;
+        }
+        idle
+    }
+}
+
+/// Keeps an eye on the idle time for the update thread, dimming when away.
+///
+/// The probing happens on a thread of its own, since gdbus can take a while and the update
+/// thread's holding the lock.
+pub struct Watcher {
+    /// Which source to poll, `None` to stop.
+    wanted: Sender<Option<IdleSource>>,
+    /// What we last told the polling thread.
+    asked: Option<Option<IdleSource>>,
+    readings: Receiver<Result<Duration, String>>,
+}
+
+impl Watcher {
+    pub fn new(probe: impl Probe) -> Self {
+        let (wanted, wanted_rx) = mpsc::channel();
+        let (readings_tx, readings) = mpsc::channel();
+        thread::spawn(move || poll(probe, &wanted_rx, &readings_tx));
+        Watcher {
+            wanted,
+            asked: None,
+            readings,
+        }
+    }
+
+    pub fn run(&mut self, dat: &mut SharedAppData) {
+        let wanted = dat.idle.enabled.then_some(dat.idle.source);
+        if self.asked != Some(wanted) {
+            self.wanted.send(wanted).ok();
+            self.asked = Some(wanted);
+        }
+
+        if !dat.idle.enabled {
+            // Nothing from before it was switched off counts.
+            self.readings.try_iter().for_each(drop);
+            dat.idle.status.clear();
+            if dat.idle.away {
+                dat.come_back();
+            }
+            return;
+        }
+        // Only the latest one matters.
+        if let Some(reading) = self.readings.try_iter().last() {
+            dat.idle_reading(reading);
+        }
+    }
+}
+
+/// Probes whichever source is wanted every [`CHECK_INTERVAL`], until the [`Watcher`] goes away.
+fn poll(
+    mut probe: impl Probe,
+    wanted: &Receiver<Option<IdleSource>>,
+    readings: &Sender<Result<Duration, String>>,
+) {
+    let mut source = None;
+    loop {
+        if let Some(source) = source {
+            let reading = probe.idle(source).map_err(|err| format!("{err:#}"));
+            if readings.send(reading).is_err() {
+                return;
+            }
+        }
+        let changed = match source {
+            Some(_) => wanted.recv_timeout(CHECK_INTERVAL),
+            // Nothing to do until there's something to watch.
+            None => wanted.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match changed {
+            Ok(wanted) => source = wanted,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+fn whole_secs(idle: Duration) -> Duration {
+    Duration::from_secs(idle.as_secs())
+}
+
+impl SharedAppData {
+    /// Goes away or comes back as `idle` crosses the timeout.
+    fn idle_reading(&mut self, idle: Result<Duration, String>) {
+        let timeout = humantime::parse_duration(&self.idle.timeout.0);
+        self.idle.timeout.1 = timeout.as_ref().err().map(|_| ());
+        let idle = match idle {
+            Ok(idle) => idle,
+            Err(err) => {
+                self.idle.status = format!("can't tell: {err}");
+                return;
+            }
+        };
+        self.idle.status = format!("idle for {}", humantime::format_duration(whole_secs(idle)));
+
+        let Ok(timeout) = timeout else {
+            return;
+        };
+        if idle >= timeout && !self.idle.away {
+            self.go_away();
+        } else if idle < timeout && self.idle.away {
+            self.come_back();
+        }
+    }
+
+    /// How much of the output makes it to the lights, 1 unless we're away.
+    pub fn idle_dim(&self) -> f32 {
+        match (self.idle.away, self.idle.action) {
+            (false, _) => 1.0,
+            (true, IdleAction::Dim(level)) => level,
+            (true, IdleAction::Off) => 0.0,
+        }
+    }
+
+    fn go_away(&mut self) {
+        self.idle.away = true;
+        if self.idle.action == IdleAction::Off {
+            self.idle.relay_before = Some(self.relay_enabled);
+            self.relay_enabled = false;
+            self.relay_changed = true;
+        }
+        // Whatever the MCU has loaded needs loading again, dimmed.
+        self.resync();
+    }
+
+    fn come_back(&mut self) {
+        self.idle.away = false;
+        if let Some(relay) = self.idle.relay_before.take() {
+            self.relay_enabled = relay;
+            self.relay_changed = true;
+        }
+        self.resync();
+    }
+}
+
+/// `ledc idle`, prints what each source makes of the idle time.
+pub fn cli(args: &[String]) -> Result<()> {
+    if !args.is_empty() {
+        bail!("ledc idle doesn't take arguments");
+    }
+    let show = |idle: Result<Duration>| match idle {
+        Ok(idle) => humantime::format_duration(whole_secs(idle)).to_string(),
+        Err(err) => format!("{err:#}"),
+    };
+    println!("x11: {}", show(X11::connect().and_then(|x11| x11.idle())));
+    println!("logind: {}", show(logind_idle()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    fn mins(n: u64) -> Result<Duration, String> {
+        Ok(Duration::from_secs(n * 60))
+    }
+
+    fn watching(action: IdleAction) -> SharedAppData {
+        let mut dat = SharedAppData::new();
+        dat.idle.enabled = true;
+        dat.idle.action = action;
+        dat.strips_changed = false;
+        dat
+    }
+
+    #[test]
+    fn dims_and_restores() {
+        let mut dat = watching(IdleAction::Dim(0.2));
+        dat.idle_reading(mins(3));
+        assert!(!dat.idle.away);
+        assert_eq!(dat.idle.status, "idle for 3m");
+        assert!(!dat.strips_changed);
+
+        dat.idle_reading(mins(10));
+        assert!(dat.idle.away);
+        assert_eq!(dat.idle_dim(), 0.2);
+        assert!(dat.strips_changed);
+
+        dat.idle_reading(Ok(Duration::from_secs(2)));
+        assert!(!dat.idle.away);
+        assert_eq!(dat.idle_dim(), 1.0);
+    }
+
+    #[test]
+    fn turns_the_relay_back_to_how_it_was() {
+        let mut dat = watching(IdleAction::Off);
+        dat.relay_enabled = true;
+        dat.idle_reading(mins(11));
+        assert!(dat.idle.away);
+        assert!(!dat.relay_enabled && dat.relay_changed);
+        assert_eq!(dat.idle.relay_before, Some(true));
+
+        dat.relay_changed = false;
+        dat.idle_reading(mins(0));
+        assert!(dat.relay_enabled && dat.relay_changed);
+        assert_eq!(dat.idle.relay_before, None);
+
+        // Off before going away stays off.
+        dat.relay_enabled = false;
+        dat.idle_reading(mins(11));
+        dat.idle_reading(mins(0));
+        assert!(!dat.relay_enabled);
+    }
+
+    #[test]
+    fn stays_away_across_polls() {
+        let mut dat = watching(IdleAction::Off);
+        dat.relay_enabled = true;
+        dat.idle_reading(mins(10));
+        // Turned on by hand while away, which the next polls mustn't undo or remember.
+        dat.relay_enabled = true;
+        dat.strips_changed = false;
+        for n in 11..20 {
+            dat.idle_reading(mins(n));
+            assert!(dat.idle.away);
+            assert!(!dat.strips_changed);
+        }
+        assert!(dat.relay_enabled);
+        assert_eq!(dat.idle.relay_before, Some(true));
+
+        // Failed readings and bad timeouts don't bring it back either.
+        dat.idle_reading(Err("no".to_string()));
+        assert_eq!(dat.idle.status, "can't tell: no");
+        dat.idle.timeout.0 = "soon".to_string();
+        dat.idle_reading(mins(0));
+        assert!(dat.idle.away);
+        assert!(dat.idle.timeout.1.is_some());
+    }
+
+    /// Says whatever's in `idle`, and what it was asked for.
+    struct FakeProbe {
+        idle: Arc<Mutex<Duration>>,
+        asked: Arc<Mutex<Vec<IdleSource>>>,
+    }
+
+    impl Probe for FakeProbe {
+        fn idle(&mut self, source: IdleSource) -> Result<Duration> {
+            self.asked.lock().unwrap().push(source);
+            Ok(*self.idle.lock().unwrap())
+        }
+    }
+
+    #[test]
+    fn polls_off_the_update_thread() {
+        let idle = Arc::new(Mutex::new(Duration::from_secs(3600)));
+        let asked = Arc::new(Mutex::new(vec![]));
+        let mut watcher = Watcher::new(FakeProbe {
+            idle: idle.clone(),
+            asked: asked.clone(),
+        });
+        let mut dat = watching(IdleAction::Dim(0.2));
+        dat.idle.source = IdleSource::Logind;
+
+        let until = |dat: &mut SharedAppData, watcher: &mut Watcher, away: bool| {
+            for _ in 0..200 {
+                watcher.run(dat);
+                if dat.idle.away == away {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            panic!("never got {away}");
+        };
+        until(&mut dat, &mut watcher, true);
+        assert_eq!(asked.lock().unwrap()[0], IdleSource::Logind);
+
+        *idle.lock().unwrap() = Duration::ZERO;
+        until(&mut dat, &mut watcher, false);
+
+        // Switching off comes back and stops the polling.
+        *idle.lock().unwrap() = Duration::from_secs(3600);
+        until(&mut dat, &mut watcher, true);
+        dat.idle.enabled = false;
+        watcher.run(&mut dat);
+        assert!(!dat.idle.away);
+        thread::sleep(CHECK_INTERVAL / 10);
+        let polls = asked.lock().unwrap().len();
+        thread::sleep(CHECK_INTERVAL + CHECK_INTERVAL / 2);
+        assert_eq!(asked.lock().unwrap().len(), polls);
+    }
+}
@@ -0,0 +1,293 @@
+//! The bincode state file from before the config, frozen as it was so it stays readable
+//! whatever happens to the types it became. Don't change these.
+
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{controller::Manual, easing::Easing, wave, Keyframe, ScheduleUi};
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Strip(u16, u16);
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum Controller {
+    Manual,
+    Wave {
+        // `started_at` was skipped, so it isn't in the file.
+        interval_ms: f32,
+        warm: bool,
+        cold: bool,
+        ty: WaveType,
+    },
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum WaveType {
+    Sine,
+    /// Square wave with duty cycle %
+    Square(f32),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Schedule {
+    start: (String, Option<()>),
+    length: (String, Option<()>),
+    endpoint: Vec<Strip>,
+    send: Option<SystemTime>,
+    status_changed: bool,
+    swap_on_stop: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SharedAppData {
+    strips: Vec<Strip>,
+    strips_changed: bool,
+    controller: Controller,
+    relay_enabled: bool,
+    relay_changed: bool,
+    schedule: Schedule,
+}
+
+impl From<Strip> for crate::Strip {
+    fn from(Strip(cold, warm): Strip) -> Self {
+        crate::Strip(cold, warm)
+    }
+}
+
+impl From<WaveType> for crate::WaveType {
+    fn from(ty: WaveType) -> Self {
+        match ty {
+            WaveType::Sine => crate::WaveType::Sine,
+            WaveType::Square(duty) => crate::WaveType::Square(duty),
+        }
+    }
+}
+
+impl Controller {
+    /// The waves used to take over the whole channel, on both strips at once.
+    fn into_new(self, strips: &[crate::Strip]) -> Box<dyn crate::controller::Controller> {
+        let Controller::Wave {
+            interval_ms,
+            warm,
+            cold,
+            ty,
+        } = self
+        else {
+            return Box::new(Manual);
+        };
+        let channel = |enabled| wave::Channel {
+            enabled,
+            ty: ty.into(),
+            interval_ms,
+            min: 0,
+            max: i32::from(u16::MAX),
+            phase: 0.0,
+        };
+        Box::new(wave::Wave {
+            started_at: SystemTime::now(),
+            base: strips
+                .iter()
+                .map(|strip| {
+                    crate::Strip(
+                        if cold { 0 } else { strip.0 },
+                        if warm { 0 } else { strip.1 },
+                    )
+                })
+                .collect(),
+            channels: vec![[channel(cold), channel(warm)]; strips.len()],
+        })
+    }
+}
+
+impl From<Schedule> for ScheduleUi {
+    /// The one fade to `endpoint` becomes a single keyframe.
+    fn from(schedule: Schedule) -> Self {
+        ScheduleUi {
+            start: schedule.start,
+            keyframes: vec![Keyframe {
+                offset: ("0s".to_string(), None),
+                duration: schedule.length,
+                target: schedule.endpoint.into_iter().map(Into::into).collect(),
+                easing: Easing::Linear,
+            }],
+            send: schedule.send,
+            status_changed: schedule.status_changed,
+            swap_on_stop: schedule.swap_on_stop,
+            ..ScheduleUi::default()
+        }
+    }
+}
+
+impl From<SharedAppData> for crate::SharedAppData {
+    /// Everything that came since starts from its defaults.
+    fn from(old: SharedAppData) -> Self {
+        let strips: Vec<crate::Strip> = old.strips.into_iter().map(Into::into).collect();
+        crate::SharedAppData {
+            controller: old.controller.into_new(&strips),
+            strips,
+            // Whatever the MCU has, it's from before.
+            strips_changed: true,
+            relay_enabled: old.relay_enabled,
+            relay_changed: old.relay_changed,
+            schedules: vec![old.schedule.into()],
+            ..crate::SharedAppData::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Mutex};
+
+    use super::*;
+    use crate::config::tests::scratch_config;
+
+    /// The tests below share the one config file.
+    static CONFIG: Mutex<()> = Mutex::new(());
+
+    /// Starts over with nothing in the config directory.
+    fn fresh() {
+        scratch_config();
+        for path in [
+            crate::SharedAppData::config_path().unwrap(),
+            crate::SharedAppData::state_path().unwrap(),
+        ] {
+            fs::remove_file(path).ok();
+        }
+    }
+
+    fn baseline(controller: Controller) -> Vec<u8> {
+        bincode::serialize(&SharedAppData {
+            strips: vec![Strip(100, 200), Strip(300, 400)],
+            strips_changed: false,
+            controller,
+            relay_enabled: true,
+            relay_changed: false,
+            schedule: Schedule {
+                start: ("7h".to_string(), None),
+                length: ("45m".to_string(), None),
+                endpoint: vec![Strip(u16::MAX, 0), Strip(1, 2)],
+                send: None,
+                status_changed: false,
+                swap_on_stop: true,
+            },
+        })
+        .unwrap()
+    }
+
+    fn migrate(blob: &[u8]) -> crate::SharedAppData {
+        bincode::deserialize::<SharedAppData>(blob).unwrap().into()
+    }
+
+    #[test]
+    fn migrates_the_baseline_state_file() {
+        let dat = migrate(&baseline(Controller::Manual));
+        assert_eq!(dat.strips, [crate::Strip(100, 200), crate::Strip(300, 400)]);
+        assert!(dat.strips_changed && dat.relay_enabled);
+        assert_eq!(dat.controller.name(), "Manual");
+        assert_eq!(dat.caps, crate::Strip(u16::MAX, u16::MAX));
+        assert!(dat.triggers.is_empty() && dat.scenes.is_empty());
+
+        let [schedule] = &dat.schedules[..] else {
+            panic!("{:?}", dat.schedules);
+        };
+        assert_eq!(schedule.start.0, "7h");
+        assert!(schedule.swap_on_stop && schedule.enabled);
+        let [keyframe] = &schedule.keyframes[..] else {
+            panic!("{:?}", schedule.keyframes);
+        };
+        assert_eq!(keyframe.offset.0, "0s");
+        assert_eq!(keyframe.duration.0, "45m");
+        assert_eq!(
+            keyframe.target,
+            [crate::Strip(u16::MAX, 0), crate::Strip(1, 2)]
+        );
+    }
+
+    #[test]
+    fn migrates_waves() {
+        let dat = migrate(&baseline(Controller::Wave {
+            interval_ms: 2000.0,
+            warm: true,
+            cold: false,
+            ty: WaveType::Square(0.25),
+        }));
+        let wave: wave::Wave = serde_json::from_value(dat.controller.save()).unwrap();
+        // The warm channels swing all the way, the cold ones stay where they were.
+        assert_eq!(wave.base, [crate::Strip(100, 0), crate::Strip(300, 0)]);
+        for [cold, warm] in &wave.channels {
+            assert!(!cold.enabled && warm.enabled);
+            assert_eq!(warm.ty, crate::WaveType::Square(0.25));
+            assert_eq!(warm.interval_ms, 2000.0);
+            assert_eq!((warm.min, warm.max), (0, i32::from(u16::MAX)));
+        }
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        let blob = baseline(Controller::Manual);
+        assert!(bincode::deserialize::<SharedAppData>(&blob[..blob.len() - 1]).is_err());
+        assert!(bincode::deserialize::<SharedAppData>(b"(version: 1, state: ())").is_err());
+    }
+
+    #[test]
+    fn reads_configs_back() {
+        let _config = CONFIG.lock().unwrap();
+        fresh();
+        assert_eq!(crate::SharedAppData::read_config().unwrap(), None);
+        let path = crate::SharedAppData::config_path().unwrap();
+
+        fs::write(&path, "(version: 1, state: ())").unwrap();
+        let dat = crate::SharedAppData::read_config().unwrap().unwrap();
+        assert_eq!(dat, crate::SharedAppData::new());
+
+        let mut dat = dat;
+        dat.strips = vec![crate::Strip(1, 2), crate::Strip(3, 4)];
+        dat.caps = crate::Strip(1000, 2000);
+        dat.controller = Box::<wave::Wave>::default();
+        dat.save_config().unwrap();
+        assert!(!path.with_extension("ron.new").exists());
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("// ledc's config."));
+        assert_eq!(crate::SharedAppData::read_config().unwrap(), Some(dat));
+    }
+
+    #[test]
+    fn refuses_newer_configs() {
+        let _config = CONFIG.lock().unwrap();
+        fresh();
+        let path = crate::SharedAppData::config_path().unwrap();
+        fs::write(&path, "(version: 2, state: (strips: []))").unwrap();
+        let err = crate::SharedAppData::read_config().unwrap_err().to_string();
+        assert!(err.contains("is from a newer ledc (version 2"), "{err}");
+        // And doesn't write over them.
+        assert!(crate::SharedAppData::load_config().is_err());
+        assert!(fs::read_to_string(&path).unwrap().contains("version: 2"));
+
+        fs::write(&path, "(state: ())").unwrap();
+        assert!(crate::SharedAppData::read_config().is_err());
+    }
+
+    #[test]
+    fn loading_migrates_the_state_file_once() {
+        let _config = CONFIG.lock().unwrap();
+        fresh();
+        let state = crate::SharedAppData::state_path().unwrap();
+        fs::write(&state, baseline(Controller::Manual)).unwrap();
+
+        let dat = crate::SharedAppData::load_config().unwrap();
+        assert_eq!(dat.schedules[0].start.0, "7h");
+        // Written out as a config, the old file left be.
+        assert_eq!(crate::SharedAppData::read_config().unwrap(), Some(dat));
+        assert!(state.exists());
+
+        // A state file that doesn't read starts from the defaults.
+        fresh();
+        fs::write(&state, b"garbage").unwrap();
+        let dat = crate::SharedAppData::load_config().unwrap();
+        assert_eq!(dat, crate::SharedAppData::new());
+        assert!(crate::SharedAppData::config_path().unwrap().exists());
+    }
+}
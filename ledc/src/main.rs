@@ -2,7 +2,8 @@
 // #![allow(clippy::must_use_candidate)]
 
 use std::{
-    sync::{atomic::AtomicBool, Arc, Mutex},
+    collections::VecDeque,
+    sync::{atomic::AtomicBool, mpsc, Arc, Mutex},
     thread::{sleep, spawn, JoinHandle},
     time::{Duration, Instant, SystemTime},
 };
@@ -10,60 +11,353 @@ use std::{
 use serde::{Deserialize, Serialize};
 use serialport::SerialPort;
 
+use crate::easing::Easing;
+
+mod animation;
+mod audio;
+mod calendar;
+mod clock;
 mod config;
+mod control;
+mod controller;
+mod easing;
+mod focus;
+mod idle;
+mod legacy;
+mod notify;
+mod preview;
+mod scene;
+mod schedule;
+mod script;
+mod simulate;
+mod solar;
+mod timespec;
+mod trigger;
 mod ui;
 mod update;
+mod wave;
 
 fn main() {
-    let options = eframe::NativeOptions::default();
-
-    eframe::run_native("ledc", options, Box::new(|_cc| Box::<LedApp>::default()));
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let result = match args.first().map(String::as_str) {
+        Some("simulate") => simulate::cli(&args[1..]),
+        Some("audio") => audio::cli(&args[1..]),
+        Some("focus") => focus::cli(&args[1..]),
+        Some("notify") => notify::cli(&args[1..]),
+        Some("idle") => idle::cli(&args[1..]),
+        Some("scene") => scene::cli(&args[1..]),
+        _ => SharedAppData::load_config().map(|mut dat| {
+            dat.recall_default();
+            let options = eframe::NativeOptions::default();
+            eframe::run_native("ledc", options, Box::new(|_cc| Box::new(LedApp::new(dat))));
+        }),
+    };
+    if let Err(err) = result {
+        eprintln!("{err:#}");
+        std::process::exit(1);
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
-struct Strip(u16, u16);
-#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
-enum Controller {
-    Manual,
-    Wave {
-        #[serde(skip)]
-        #[serde(default = "Instant::now")] // gets thrown away anyway
-        started_at: Instant,
-        interval_ms: f32,
-        warm: bool,
-        cold: bool,
-        ty: WaveType,
-    },
-}
-
+pub struct Strip(u16, u16);
 #[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
 enum WaveType {
     Sine,
     /// Square wave with duty cycle %
     Square(f32),
+    Triangle,
+    /// Ramps up, then drops.
+    Sawtooth,
+    /// Slow in, quick out, like breathing. Lingers near the bottom.
+    Breathe,
+    /// Wanders between random levels, one per period.
+    Noise,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+struct Keyframe {
+    /// How long to hold before this segment, counted from the end of the previous one.
+    offset: (String, Option<()>),
+    duration: (String, Option<()>),
+    target: Vec<Strip>,
+    easing: Easing,
+}
+
+/// Re-arms a schedule by itself, at a local time of day on some weekdays.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+struct Recurrence {
+    enabled: bool,
+    /// `HH:MM[:SS]`, in local time.
+    time: (String, Option<()>),
+    /// Monday first.
+    weekdays: [bool; 7],
+    /// Skip the next occurrence. Cleared once the run after it finishes.
+    skip_next: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
 struct ScheduleUi {
-    /// incredibly dumb type on `start` and
-    /// `length` but its gonna work
+    name: String,
+    /// Disabled schedules never arm or run.
+    enabled: bool,
+    /// Decides who wins when running schedules overlap, see [`schedule::pick`].
+    priority: i32,
+    /// incredibly dumb type on `start` and the
+    /// keyframe durations but its gonna work
     /// .0 is content, .1 is validity (Some is invalid)
     start: (String, Option<()>),
-    length: (String, Option<()>),
-    endpoint: Vec<Strip>,
-    send: Option<SystemTime>, // TODO: Set to none once `length` has passed.
+    keyframes: Vec<Keyframe>,
+    /// When enabled, replaces `start`.
+    recurrence: Recurrence,
+    send: Option<SystemTime>,
+    /// Index of the keyframe we last handed to the MCU (or are streaming).
+    segment: usize,
+    /// The MCU doesn't have this schedule's current state, it'll need (re)loading
+    /// once it gets the lights.
     status_changed: bool,
     swap_on_stop: bool,
+    /// Why the timeline is too long for the MCU, if it is.
+    #[serde(skip)]
+    range_error: Option<String>,
+}
+
+/// Something a trigger does when it fires.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+enum Action {
+    /// Set the strips straight away.
+    SetStrips(Vec<Strip>),
+    /// Switch to a wave with these settings.
+    Wave(wave::Wave),
+    /// Start the schedule with this name.
+    StartSchedule(String),
+    Relay(RelayAction),
+    /// Recall the scene with this name.
+    RecallScene {
+        name: String,
+        /// How long to fade into it for, as a humantime duration.
+        fade: (String, Option<()>),
+    },
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone)]
+enum RelayAction {
+    On,
+    Off,
+    Toggle,
+}
+
+/// Runs an [`Action`] whenever a cron expression fires.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+struct Trigger {
+    name: String,
+    enabled: bool,
+    /// `sec min hour day-of-month month day-of-week [year]`, see [`Trigger::upcoming`].
+    cron: (String, Option<()>),
+    /// IANA name like `Europe/Amsterdam`, empty for local time.
+    timezone: (String, Option<()>),
+    action: Action,
+    /// Worked out by the update thread, `None` when it needs redoing.
+    #[serde(skip)]
+    next: Option<SystemTime>,
+}
+
+/// Which part of a calendar event a [`CalendarRule`] fires on.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone)]
+enum CalendarEdge {
+    Start,
+    End,
+    /// The end of the day's last matching event.
+    LastEnd,
+}
+
+/// Runs an [`Action`] around the events in a calendar.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+struct CalendarRule {
+    /// Matched against the summary (contained in it) and categories (equal to one),
+    /// ignoring case. Empty matches every event.
+    matching: String,
+    edge: CalendarEdge,
+    /// How long before the edge to fire, as a humantime duration.
+    lead: (String, Option<()>),
+    action: Action,
+}
+
+/// A local iCalendar (.ics) file, reloaded whenever it changes.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+struct Calendar {
+    path: String,
+    enabled: bool,
+    rules: Vec<CalendarRule>,
+    /// What the update thread made of the file.
+    #[serde(skip)]
+    status: String,
+    /// When rules fire next, with the index of the rule.
+    #[serde(skip)]
+    upcoming: Vec<(SystemTime, usize)>,
 }
 
+/// Dims the lights (or turns them off) while nobody's at the desk.
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+struct Idle {
+    enabled: bool,
+    source: IdleSource,
+    /// How long without input counts as away, as a humantime duration.
+    timeout: (String, Option<()>),
+    action: IdleAction,
+    /// What the update thread made of the idle time.
+    #[serde(skip)]
+    status: String,
+    #[serde(skip)]
+    away: bool,
+    /// What the relay was at before we turned it off, to put it back.
+    #[serde(skip)]
+    relay_before: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone)]
+enum IdleSource {
+    /// X11 if there's a display, logind otherwise.
+    Auto,
+    /// The screensaver extension's time since input.
+    X11,
+    /// The session's IdleHint, as set by the desktop.
+    Logind,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+enum IdleAction {
+    /// Down to this much of whatever's showing.
+    Dim(f32),
+    /// Lights and relay off.
+    Off,
+}
+
+/// The strips and relay, saved under a name to come back to.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+#[serde(default)]
+struct Scene {
+    name: String,
+    strips: Vec<Strip>,
+    relay: bool,
+}
+
+/// The strips on their way into a recalled [`Scene`].
+#[derive(PartialEq, Debug, Clone)]
+struct Fade {
+    from: Vec<Strip>,
+    to: Vec<Strip>,
+    started_at: Instant,
+    length: Duration,
+    /// Turning the relay off waits until the lights are down.
+    relay_off: bool,
+}
+
+/// Keyframes on every strip channel, looped or played once by [`animation::Player`].
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Animation {
+    name: String,
+    /// In seconds. Keys past the end never get reached.
+    length: f32,
+    playback: Playback,
+    /// Cold and warm, for each strip. Each track is kept in order of time.
+    tracks: Vec<[Vec<AnimationKey>; 2]>,
+    /// When it started playing, if it is.
+    #[serde(skip)]
+    started_at: Option<SystemTime>,
+}
+
+/// One channel's value at some point in an [`Animation`].
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+#[serde(default)]
+struct AnimationKey {
+    /// Seconds from the start.
+    at: f32,
+    value: u16,
+    /// How it gets from here to the next key.
+    easing: Easing,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Default)]
+enum Playback {
+    #[default]
+    Loop,
+    /// Forwards, then backwards.
+    PingPong,
+    /// Once through, then holds the end.
+    Once,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
 pub struct SharedAppData {
     strips: Vec<Strip>,
     strips_changed: bool,
-    controller: Controller,
+    /// No channel goes above these, whatever's driving it.
+    caps: Strip,
+    controller: Box<dyn controller::Controller>,
     relay_enabled: bool,
     relay_changed: bool,
-    schedule: ScheduleUi,
+    schedules: Vec<ScheduleUi>,
+    triggers: Vec<Trigger>,
+    calendars: Vec<Calendar>,
+    idle: Idle,
+    animations: Vec<Animation>,
+    scenes: Vec<Scene>,
+    /// Recalled whenever we start.
+    default_scene: Option<String>,
+    /// Has the lights to itself while it plays.
+    #[serde(skip)]
+    preview: Option<Preview>,
+    #[serde(skip)]
+    notifications: Notifications,
+    #[serde(skip)]
+    fade: Option<Fade>,
+}
+
+/// A schedule's keyframes played back on the strips faster than real time.
+#[derive(PartialEq, Debug, Clone)]
+struct Preview {
+    name: String,
+    /// Starting at zero, the schedule's start isn't waited out.
+    segments: Vec<schedule::Segment>,
+    /// What the strips were at before.
+    origin: Vec<Strip>,
+    speed: f32,
+    started_at: Instant,
+}
+
+/// A flash or pulse over whatever the lights are doing, to get attention.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Notification {
+    pattern: Pattern,
+    count: u32,
+    /// 0 for cold, 1 for warm.
+    warmth: f32,
+}
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+enum Pattern {
+    Flash,
+    Pulse,
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+struct Notifications {
+    queue: VecDeque<Notification>,
+    /// The one on the lights, and since when.
+    playing: Option<(Notification, SystemTime)>,
+    /// To space them out.
+    finished_at: Option<SystemTime>,
+    /// When the last few were queued, for rate limiting.
+    accepted: VecDeque<SystemTime>,
 }
 
 struct LedApp {
@@ -74,6 +368,20 @@ struct LedApp {
     first_render: bool,
     poll_update_fast: bool,
     config_thread_flag: Arc<AtomicBool>,
+    /// How far ahead to simulate for the plot.
+    simulation_span: (String, Option<()>),
+    simulation: Option<Vec<simulate::Sample>>,
+    preview_speed: f32,
+    /// What to import an animation from.
+    animation_file: String,
+    /// How the last import or export went.
+    animation_status: String,
+    /// What to save the strips as, see [`Scene`].
+    scene_name: String,
+    /// How long recalling a scene from the UI fades for.
+    scene_fade: (String, Option<()>),
+    /// The scripts to offer as control modes.
+    scripts: script::Available,
 }
 
 pub fn open_serial() -> Box<dyn SerialPort> {
@@ -100,15 +408,21 @@ pub fn open_serial() -> Box<dyn SerialPort> {
     }
 }
 
-impl Default for LedApp {
-    fn default() -> Self {
-        let shared_dat = Mutex::new(SharedAppData::load_config().unwrap());
+impl LedApp {
+    fn new(dat: SharedAppData) -> Self {
+        let shared_dat = Mutex::new(dat);
 
         let display_arc = Arc::new(shared_dat);
         let update_arc = Arc::clone(&display_arc);
         let config_arc = Arc::clone(&display_arc);
 
-        let update_thread = spawn(move || update::update_thread(update_arc).unwrap());
+        let (sleep_tx, sleep_rx) = mpsc::channel();
+        clock::watch_logind(sleep_tx);
+        let update_thread = spawn(move || {
+            update::update_thread(update_arc, Box::<clock::SystemClock>::default(), sleep_rx)
+                .unwrap()
+        });
+        control::serve(Arc::clone(&display_arc));
         let config_thread_flag = Arc::new(AtomicBool::new(true));
         let config_thread_flag2 = Arc::clone(&config_thread_flag);
         let config_thread = spawn(move || config::config_thread(config_arc, config_thread_flag2));
@@ -120,6 +434,14 @@ impl Default for LedApp {
             config_thread_flag,
             first_render: true,
             poll_update_fast: true, // TODO try false for startup cpu% maybe?
+            simulation_span: ("1d".to_string(), None),
+            simulation: None,
+            preview_speed: 60.0,
+            animation_file: String::new(),
+            animation_status: String::new(),
+            scene_name: String::new(),
+            scene_fade: ("0s".to_string(), None),
+            scripts: script::Available::default(),
         }
     }
 }
@@ -0,0 +1,300 @@
+use std::{
+    f64::consts::TAU,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{control, schedule, Notification, Pattern, SharedAppData, Strip};
+
+/// How long one flash or pulse takes.
+const FLASH_PERIOD: Duration = Duration::from_millis(400);
+const PULSE_PERIOD: Duration = Duration::from_millis(1200);
+const MAX_COUNT: u32 = 10;
+/// How long the lights get back between two notifications.
+const GAP: Duration = Duration::from_secs(1);
+const MAX_QUEUED: usize = 8;
+/// At most this many a minute get through, so a flaky CI can't strobe the desk.
+const MAX_PER_MINUTE: usize = 10;
+/// What the strips' whites are, roughly, for picking between them by colour temperature.
+const COLD_KELVIN: f32 = 6500.0;
+const WARM_KELVIN: f32 = 2700.0;
+
+impl Notification {
+    /// From `PATTERN COUNT TEMPERATURE`, like `flash 3 warm` or `pulse 2 4000K`.
+    ///
+    /// The temperature is `cold`, `neutral`, `warm`, kelvin, or 0 (cold) to 1 (warm).
+    pub fn parse(args: &[&str]) -> Result<Self> {
+        let [pattern, count, temperature] = args else {
+            bail!("usage: notify flash|pulse COUNT cold|neutral|warm|KELVINK|0-1");
+        };
+        let pattern = match *pattern {
+            "flash" => Pattern::Flash,
+            "pulse" => Pattern::Pulse,
+            _ => bail!("unknown pattern {pattern:?}, try flash or pulse"),
+        };
+        let count = count.parse::<u32>()?;
+        if !(1..=MAX_COUNT).contains(&count) {
+            bail!("count has to be 1 to {MAX_COUNT}");
+        }
+        let warmth = match *temperature {
+            "cold" => 0.0,
+            "neutral" => 0.5,
+            "warm" => 1.0,
+            kelvin if kelvin.ends_with(['K', 'k']) => {
+                let kelvin = kelvin[..kelvin.len() - 1].parse::<f32>()?;
+                ((COLD_KELVIN - kelvin) / (COLD_KELVIN - WARM_KELVIN)).clamp(0.0, 1.0)
+            }
+            warmth => warmth
+                .parse::<f32>()
+                .ok()
+                .filter(|warmth| (0.0..=1.0).contains(warmth))
+                .ok_or_else(|| anyhow!("unknown colour temperature {warmth:?}"))?,
+        };
+        Ok(Notification {
+            pattern,
+            count,
+            warmth,
+        })
+    }
+
+    fn length(&self) -> Duration {
+        self.period() * self.count
+    }
+
+    fn period(&self) -> Duration {
+        match self.pattern {
+            Pattern::Flash => FLASH_PERIOD,
+            Pattern::Pulse => PULSE_PERIOD,
+        }
+    }
+
+    /// How much of the notification's colour shows `elapsed` in, 0 to 1.
+    fn level(&self, elapsed: Duration) -> f64 {
+        let phase = (elapsed.as_secs_f64() / self.period().as_secs_f64()).fract();
+        match self.pattern {
+            Pattern::Flash => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            // Up from nothing and back.
+            Pattern::Pulse => 0.5 - 0.5 * (TAU * phase).cos(),
+        }
+    }
+
+    /// Both whites, balanced by warmth. Neutral has them both full on.
+    fn colour(&self) -> Strip {
+        let channel = |share: f32| ((share * 2.0).min(1.0) * f32::from(u16::MAX)) as u16;
+        Strip(channel(1.0 - self.warmth), channel(self.warmth))
+    }
+
+    fn frame(&self, under: &[Strip], elapsed: Duration) -> Vec<Strip> {
+        let level = self.level(elapsed);
+        let colour = self.colour();
+        let lerp = |a: u16, b: u16| (f64::from(a) + (f64::from(b) - f64::from(a)) * level) as u16;
+        under
+            .iter()
+            .map(|strip| Strip(lerp(strip.0, colour.0), lerp(strip.1, colour.1)))
+            .collect()
+    }
+}
+
+impl SharedAppData {
+    /// Queues `notification`, unless too many came in lately.
+    pub fn notify(&mut self, notification: Notification, now: SystemTime) -> Result<()> {
+        let notifications = &mut self.notifications;
+        while notifications
+            .accepted
+            .front()
+            .is_some_and(|at| since(*at, now) >= Duration::from_secs(60))
+        {
+            notifications.accepted.pop_front();
+        }
+        if notifications.accepted.len() >= MAX_PER_MINUTE {
+            bail!("rate limited, {MAX_PER_MINUTE} a minute at most");
+        }
+        if notifications.queue.len() >= MAX_QUEUED {
+            bail!("{MAX_QUEUED} notifications already queued");
+        }
+        notifications.accepted.push_back(now);
+        notifications.queue.push_back(notification);
+        Ok(())
+    }
+
+    /// What the lights look like with the notification that's up (if any) on top, moving on
+    /// to the next queued one when it's time. Puts the lights back when one finishes.
+    pub fn notification_frame(&mut self, now: SystemTime) -> Option<Vec<Strip>> {
+        loop {
+            if let Some((notification, started_at)) = &self.notifications.playing {
+                let elapsed = since(*started_at, now);
+                if elapsed < notification.length() {
+                    let notification = notification.clone();
+                    return Some(notification.frame(&self.underneath(now), elapsed));
+                }
+                self.notifications.playing = None;
+                self.notifications.finished_at = Some(now);
+                self.resync();
+            }
+            if self
+                .notifications
+                .finished_at
+                .is_some_and(|at| since(at, now) < GAP)
+            {
+                return None;
+            }
+            let next = self.notifications.queue.pop_front()?;
+            self.notifications.playing = Some((next, now));
+        }
+    }
+
+    /// What the lights would be showing at `now` without a notification: the schedule that
+    /// has them, or else the controller's output.
+    fn underneath(&mut self, now: SystemTime) -> Vec<Strip> {
+        let timelines = schedule::timelines(&mut self.schedules);
+        let windows = schedule::windows(&self.schedules, &timelines);
+        let scheduled = schedule::pick(&windows, now).and_then(|pick| {
+            let segments = timelines[pick.index].as_ref()?;
+            let elapsed = now.duration_since(self.schedules[pick.index].send?).ok()?;
+            let running = segments.first()?.begin <= elapsed && !segments.last()?.reached(elapsed);
+            running.then(|| schedule::state_at(segments, &self.strips, elapsed))
+        });
+        scheduled.unwrap_or_else(|| self.strips.clone())
+    }
+}
+
+fn since(then: SystemTime, now: SystemTime) -> Duration {
+    now.duration_since(then).unwrap_or_default()
+}
+
+/// `ledc notify flash|pulse COUNT TEMPERATURE`, see [`Notification::parse`].
+pub fn cli(args: &[String]) -> Result<()> {
+    println!(
+        "{}",
+        control::request(&format!("notify {}", args.join(" ")), false)?
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{timespec::tests::at, Keyframe, ScheduleUi};
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn flash(count: u32, temperature: &str) -> Notification {
+        Notification::parse(&["flash", &count.to_string(), temperature]).unwrap()
+    }
+
+    /// Fading both strips from off to full cold over ten minutes, from `start`.
+    fn fading(start: SystemTime) -> SharedAppData {
+        let mut dat = SharedAppData::new();
+        dat.strips = vec![Strip(0, 0); 2];
+        let mut schedule = ScheduleUi::default();
+        schedule.start.0 = "0s".to_string();
+        schedule.keyframes = vec![Keyframe {
+            duration: ("10m".to_string(), None),
+            ..Keyframe::default()
+        }];
+        schedule.send = Some(start);
+        dat.schedules = vec![schedule];
+        dat
+    }
+
+    #[test]
+    fn parses() {
+        let pulse = Notification::parse(&["pulse", "2", "4600K"]).unwrap();
+        assert_eq!(pulse.pattern, Pattern::Pulse);
+        assert_eq!(pulse.warmth, 0.5);
+        assert_eq!(pulse.length(), 2 * PULSE_PERIOD);
+        assert_eq!(flash(1, "neutral").colour(), Strip(u16::MAX, u16::MAX));
+        assert_eq!(flash(1, "0.25").colour().1, u16::MAX / 2);
+        for args in [
+            &["flash", "0", "warm"][..],
+            &["flash", "11", "warm"],
+            &["blink", "1", "warm"],
+            &["flash", "1", "blue"],
+            &["flash", "1", "1.5"],
+            &["flash", "1"],
+        ] {
+            assert!(Notification::parse(args).is_err(), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn plays_over_a_running_schedule_then_resyncs() {
+        let start = at("2023-07-14T12:00:00Z");
+        let mut dat = fading(start);
+        let now = start + 5 * MINUTE;
+        dat.notify(flash(2, "warm"), now).unwrap();
+        dat.schedules[0].status_changed = false;
+        dat.strips_changed = false;
+
+        // On: the notification's colour.
+        assert_eq!(
+            dat.notification_frame(now),
+            Some(vec![Strip(0, u16::MAX); 2])
+        );
+        // Off: wherever the fade has got to by then, not the strips it started from.
+        let off = now + FLASH_PERIOD * 3 / 4;
+        let segments = schedule::timelines(&mut dat.schedules)[0].clone().unwrap();
+        let under = schedule::state_at(&segments, &dat.strips, off.duration_since(start).unwrap());
+        assert!(under[0].0 > u16::MAX / 2, "{under:?}");
+        assert_eq!(dat.notification_frame(off), Some(under));
+        assert!(!dat.schedules[0].status_changed && !dat.strips_changed);
+
+        // Once it's done the MCU needs the interrupted segment again.
+        assert_eq!(dat.notification_frame(now + 2 * FLASH_PERIOD), None);
+        assert!(dat.schedules[0].status_changed && dat.strips_changed);
+        assert_eq!(dat.notifications.playing, None);
+        assert_eq!(dat.schedules[0].send, Some(start));
+    }
+
+    #[test]
+    fn queues_in_order_with_a_gap() {
+        let now = at("2023-07-14T12:00:00Z");
+        let mut dat = SharedAppData::new();
+        dat.notify(flash(1, "cold"), now).unwrap();
+        dat.notify(flash(1, "warm"), now).unwrap();
+
+        assert_eq!(
+            dat.notification_frame(now),
+            Some(vec![Strip(u16::MAX, 0); 2])
+        );
+        let done = now + FLASH_PERIOD;
+        assert_eq!(dat.notification_frame(done), None);
+        assert_eq!(dat.notification_frame(done + GAP / 2), None);
+        assert_eq!(
+            dat.notification_frame(done + GAP),
+            Some(vec![Strip(0, u16::MAX); 2])
+        );
+        assert!(dat.notifications.queue.is_empty());
+    }
+
+    #[test]
+    fn limits_the_rate() {
+        let now = at("2023-07-14T12:00:00Z");
+        let mut dat = SharedAppData::new();
+        for _ in 0..MAX_QUEUED {
+            dat.notify(flash(1, "warm"), now).unwrap();
+        }
+        let err = dat.notify(flash(1, "warm"), now).unwrap_err();
+        assert_eq!(err.to_string(), "8 notifications already queued");
+
+        // Played through, so there's room in the queue but not in the minute.
+        dat.notifications.queue.clear();
+        for _ in MAX_QUEUED..MAX_PER_MINUTE {
+            dat.notify(flash(1, "warm"), now + MINUTE / 2).unwrap();
+        }
+        dat.notifications.queue.clear();
+        let err = dat.notify(flash(1, "warm"), now + MINUTE / 2).unwrap_err();
+        assert_eq!(err.to_string(), "rate limited, 10 a minute at most");
+
+        // The first eight have aged out, the last two haven't.
+        dat.notify(flash(1, "warm"), now + MINUTE).unwrap();
+        assert_eq!(dat.notifications.accepted.len(), 3);
+    }
+}
@@ -0,0 +1,136 @@
+use std::time::Instant;
+
+use crate::{schedule, Preview, ScheduleUi, SharedAppData, Strip};
+
+impl Preview {
+    /// Plays `schedule` from `origin` at `speed` times real time, if its timeline parses.
+    pub fn new(schedule: &mut ScheduleUi, origin: Vec<Strip>, speed: f32) -> Option<Self> {
+        let mut segments = schedule.timeline()?;
+        let start = segments.first()?.begin;
+        for segment in &mut segments {
+            segment.begin -= start;
+        }
+        Some(Preview {
+            name: schedule.name.clone(),
+            segments,
+            origin,
+            speed,
+            started_at: Instant::now(),
+        })
+    }
+
+    /// How far into the schedule the preview has got, 0 to 1.
+    pub fn progress(&self) -> f32 {
+        let total = self.segments.last().map_or(0.0, |s| s.end().as_secs_f32());
+        if total == 0.0 {
+            return 1.0;
+        }
+        (self.started_at.elapsed().as_secs_f32() * self.speed / total).min(1.0)
+    }
+
+    /// What the strips look like right now, `None` once it's over.
+    pub fn frame(&self) -> Option<Vec<Strip>> {
+        let elapsed = self.started_at.elapsed().mul_f32(self.speed);
+        if elapsed > self.segments.last()?.end() {
+            return None;
+        }
+        Some(schedule::state_at(&self.segments, &self.origin, elapsed))
+    }
+}
+
+impl SharedAppData {
+    /// Ends the preview and puts the lights back.
+    pub fn stop_preview(&mut self) {
+        self.preview = None;
+        self.resync();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+    use crate::Keyframe;
+
+    const SPEED: f32 = 60.0;
+
+    /// Holding for a minute, then fading both strips up to full cold over ten.
+    fn sunrise() -> ScheduleUi {
+        let mut schedule = ScheduleUi::new("Sunrise".into());
+        schedule.keyframes = vec![Keyframe {
+            offset: ("1m".into(), None),
+            duration: ("10m".into(), None),
+            ..Keyframe::default()
+        }];
+        schedule
+    }
+
+    /// `secs` real seconds into `preview`.
+    fn after(preview: &mut Preview, secs: u64) -> Option<Vec<Strip>> {
+        preview.started_at = Instant::now() - Duration::from_secs(secs);
+        preview.frame()
+    }
+
+    #[test]
+    fn plays_at_speed_then_ends() {
+        let mut schedule = sunrise();
+        let origin = vec![Strip(0, 0); 2];
+        let mut preview = Preview::new(&mut schedule, origin.clone(), SPEED).unwrap();
+        assert_eq!(preview.name, "Sunrise");
+        // The hours before it begins are left out, the first hold with them.
+        assert_eq!(preview.segments[0].begin, Duration::ZERO);
+
+        let segments = preview.segments.clone();
+        let at = |minutes: u64| {
+            schedule::state_at(&segments, &origin, Duration::from_secs(minutes * 60))
+        };
+        // Give or take the time the test takes.
+        let near = |frame: Option<Vec<Strip>>, want: Vec<Strip>| {
+            let frame = frame.unwrap();
+            assert!(
+                frame
+                    .iter()
+                    .zip(&want)
+                    .all(|(a, b)| a.0.abs_diff(b.0) < 100 && a.1.abs_diff(b.1) < 100),
+                "{frame:?} isn't {want:?}"
+            );
+        };
+        near(after(&mut preview, 0), origin.clone());
+        // Real seconds are minutes.
+        let halfway = at(5);
+        assert!(
+            halfway[0].0 > 30_000 && halfway[0].0 < 35_000,
+            "{halfway:?}"
+        );
+        near(after(&mut preview, 5), halfway);
+        assert!((preview.progress() - 0.5).abs() < 0.01);
+        near(after(&mut preview, 9), at(9));
+        assert_eq!(after(&mut preview, 11), None);
+        assert_eq!(preview.progress(), 1.0);
+    }
+
+    #[test]
+    fn needs_a_timeline() {
+        let mut schedule = sunrise();
+        schedule.keyframes[0].duration.0 = "a while".into();
+        assert!(Preview::new(&mut schedule, vec![], SPEED).is_none());
+    }
+
+    #[test]
+    fn stopping_puts_the_schedule_back() {
+        let mut dat = SharedAppData::new();
+        let armed = SystemTime::now();
+        dat.schedules = vec![sunrise()];
+        dat.schedules[0].send = Some(armed);
+        dat.preview = Preview::new(&mut dat.schedules[0], dat.strips.clone(), SPEED);
+        assert!(dat.preview.is_some());
+        dat.strips_changed = false;
+
+        dat.stop_preview();
+        assert!(dat.preview.is_none());
+        assert_eq!(dat.schedules[0].send, Some(armed));
+        // The MCU gets the real segment again.
+        assert!(dat.schedules[0].status_changed && dat.strips_changed);
+    }
+}
@@ -0,0 +1,312 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{control, controller::Manual, easing::Easing, Fade, Scene, SharedAppData, Strip};
+
+impl Fade {
+    /// The strips `t` of the way there, 0 to 1.
+    fn frame(&self, t: f32) -> Vec<Strip> {
+        // Even steps in perceived brightness, so it doesn't rush through the dim end.
+        let easing = Easing::Perceptual;
+        self.to
+            .iter()
+            .enumerate()
+            .map(|(i, to)| {
+                let from = self.from.get(i).unwrap_or(to);
+                Strip(
+                    easing.interpolate(from.0, to.0, t),
+                    easing.interpolate(from.1, to.1, t),
+                )
+            })
+            .collect()
+    }
+}
+
+impl SharedAppData {
+    /// Saves the strips and relay as `name`, over the scene already called that if there is one.
+    pub fn save_scene(&mut self, name: &str) -> Result<()> {
+        if name.trim().is_empty() {
+            bail!("scenes need a name");
+        }
+        let scene = Scene {
+            name: name.to_string(),
+            strips: self.strips.clone(),
+            relay: self.relay_enabled,
+        };
+        match self.scenes.iter_mut().find(|scene| scene.name == name) {
+            Some(existing) => *existing = scene,
+            None => self.scenes.push(scene),
+        }
+        Ok(())
+    }
+
+    /// Puts the lights back the way scene `name` has them, fading over `fade`.
+    pub fn recall(&mut self, name: &str, fade: Duration) -> Result<()> {
+        let scene = self
+            .scenes
+            .iter()
+            .find(|scene| scene.name == name)
+            .ok_or_else(|| anyhow!("there's no scene called {name:?}"))?
+            .clone();
+
+        // A fixed state takes over from whatever was animating.
+        self.controller = Box::new(Manual);
+        let relay_off = self.relay_enabled && !scene.relay;
+        self.fade = (!fade.is_zero()).then(|| Fade {
+            from: self.strips.clone(),
+            to: scene.strips.clone(),
+            started_at: Instant::now(),
+            length: fade,
+            relay_off,
+        });
+        self.strips = scene.strips;
+        self.strips_changed = true;
+        if scene.relay != self.relay_enabled && !(relay_off && self.fade.is_some()) {
+            self.relay_enabled = scene.relay;
+            self.relay_changed = true;
+        }
+        Ok(())
+    }
+
+    /// Recalls [`SharedAppData::default_scene`], if there is one.
+    pub fn recall_default(&mut self) {
+        let Some(name) = self.default_scene.clone() else {
+            return;
+        };
+        if let Err(err) = self.recall(&name, Duration::ZERO) {
+            eprintln!("not recalling the default scene: {err:#}");
+        }
+    }
+
+    /// Where the strips are at in the fade, if one's going. Gives up on it once something
+    /// other than the scene has the lights.
+    pub fn fade_frame(&mut self) -> Option<Vec<Strip>> {
+        if self.controller.name() != "Manual" {
+            self.fade = None;
+        }
+        let fade = self.fade.as_ref()?;
+        let t = fade.started_at.elapsed().as_secs_f32() / fade.length.as_secs_f32();
+        if t < 1.0 {
+            return Some(fade.frame(t));
+        }
+
+        let fade = self.fade.take()?;
+        if fade.relay_off {
+            self.relay_enabled = false;
+            self.relay_changed = true;
+        }
+        Some(fade.to)
+    }
+
+    fn scene_names(&self) -> String {
+        if self.scenes.is_empty() {
+            return "no scenes".into();
+        }
+        self.scenes
+            .iter()
+            .map(|scene| {
+                if self.default_scene.as_ref() == Some(&scene.name) {
+                    format!("{} (default)", scene.name)
+                } else {
+                    scene.name.clone()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// `scene …` from the control socket.
+pub fn command(dat: &mut SharedAppData, args: &[&str]) -> Result<String> {
+    match args {
+        ["list"] => Ok(dat.scene_names()),
+        ["save", name @ ..] if !name.is_empty() => {
+            let name = name.join(" ");
+            dat.save_scene(&name)?;
+            Ok(format!("saved {name}"))
+        }
+        ["recall", name @ .., "--fade", fade] if !name.is_empty() => {
+            let name = name.join(" ");
+            dat.recall(&name, humantime::parse_duration(fade)?)?;
+            Ok(format!("fading into {name} over {fade}"))
+        }
+        ["recall", name @ ..] if !name.is_empty() => {
+            let name = name.join(" ");
+            dat.recall(&name, Duration::ZERO)?;
+            Ok(format!("recalled {name}"))
+        }
+        ["default"] => Ok(dat.default_scene.clone().unwrap_or_else(|| "none".into())),
+        ["default", "none"] => {
+            dat.default_scene = None;
+            Ok("no default scene".into())
+        }
+        ["default", name @ ..] => {
+            let name = name.join(" ");
+            if !dat.scenes.iter().any(|scene| scene.name == name) {
+                bail!("there's no scene called {name:?}");
+            }
+            dat.default_scene = Some(name.clone());
+            Ok(format!("{name} is the default"))
+        }
+        ["remove", name @ ..] if !name.is_empty() => {
+            let name = name.join(" ");
+            let before = dat.scenes.len();
+            dat.scenes.retain(|scene| scene.name != name);
+            if dat.scenes.len() == before {
+                bail!("there's no scene called {name:?}");
+            }
+            if dat.default_scene.as_ref() == Some(&name) {
+                dat.default_scene = None;
+            }
+            Ok(format!("removed {name}"))
+        }
+        _ => bail!(
+            "usage: scene list|save NAME|recall NAME [--fade DURATION]|default [NAME|none]|remove NAME"
+        ),
+    }
+}
+
+/// `ledc scene list|save|recall|default|remove`, see [`command`].
+pub fn cli(args: &[String]) -> Result<()> {
+    println!(
+        "{}",
+        control::request(&format!("scene {}", args.join(" ")), true)?
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wave::Wave;
+
+    const FADE: Duration = Duration::from_secs(10);
+
+    /// Lights full on with the relay on, and a dark scene with it off.
+    fn lit() -> SharedAppData {
+        let mut dat = SharedAppData::new();
+        dat.strips = vec![Strip(u16::MAX, u16::MAX); 2];
+        dat.relay_enabled = true;
+        dat.scenes.push(Scene {
+            name: "dark".to_string(),
+            strips: vec![Strip(0, 0); 2],
+            relay: false,
+        });
+        dat.strips_changed = false;
+        dat
+    }
+
+    /// Moves the fade `by` along.
+    fn wind(dat: &mut SharedAppData, by: Duration) {
+        let fade = dat.fade.as_mut().unwrap();
+        fade.started_at -= by;
+    }
+
+    #[test]
+    fn recalls_straight_away_without_a_fade() {
+        let mut dat = lit();
+        dat.controller = Box::new(Wave::default());
+        dat.recall("dark", Duration::ZERO).unwrap();
+        assert_eq!(dat.controller.name(), "Manual");
+        assert_eq!(dat.fade, None);
+        assert_eq!(dat.strips, vec![Strip(0, 0); 2]);
+        assert!(dat.strips_changed);
+        assert!(!dat.relay_enabled && dat.relay_changed);
+        assert_eq!(dat.fade_frame(), None);
+
+        let err = dat.recall("bright", FADE).unwrap_err().to_string();
+        assert_eq!(err, "there's no scene called \"bright\"");
+    }
+
+    #[test]
+    fn fades_then_turns_the_relay_off() {
+        let mut dat = lit();
+        dat.recall("dark", FADE).unwrap();
+        // The strips hold where they're headed, the relay waits for the lights to go down.
+        assert_eq!(dat.strips, vec![Strip(0, 0); 2]);
+        assert!(dat.relay_enabled && !dat.relay_changed);
+
+        let start = dat.fade_frame().unwrap();
+        assert!(start.iter().all(|strip| strip.0 > 60_000), "{start:?}");
+        wind(&mut dat, FADE / 2);
+        let half = dat.fade_frame().unwrap();
+        let want = Easing::Perceptual.interpolate(u16::MAX, 0, 0.5);
+        assert!(
+            half.iter().all(|strip| strip.0.abs_diff(want) < 500),
+            "{half:?}"
+        );
+        assert!(dat.relay_enabled);
+
+        wind(&mut dat, FADE / 2);
+        assert_eq!(dat.fade_frame(), Some(vec![Strip(0, 0); 2]));
+        assert_eq!(dat.fade, None);
+        assert!(!dat.relay_enabled && dat.relay_changed);
+        assert_eq!(dat.fade_frame(), None);
+    }
+
+    #[test]
+    fn turns_the_relay_on_before_fading_up() {
+        let mut dat = lit();
+        dat.relay_enabled = false;
+        dat.scenes[0].relay = true;
+        dat.strips = vec![Strip(0, 0)];
+        dat.scenes[0].strips = vec![Strip(1000, 0); 2];
+        dat.recall("dark", FADE).unwrap();
+        assert!(dat.relay_enabled && dat.relay_changed);
+        assert!(!dat.fade.as_ref().unwrap().relay_off);
+
+        // A strip that wasn't there before starts where it's going.
+        let frame = dat.fade_frame().unwrap();
+        assert!(frame[0].0 < 100, "{frame:?}");
+        assert_eq!(frame[1], Strip(1000, 0));
+    }
+
+    #[test]
+    fn gives_up_when_something_else_takes_over() {
+        let mut dat = lit();
+        dat.recall("dark", FADE).unwrap();
+        dat.controller = Box::new(Wave::default());
+        assert_eq!(dat.fade_frame(), None);
+        assert_eq!(dat.fade, None);
+        // The relay's left as it was, not turned off late.
+        assert!(dat.relay_enabled && !dat.relay_changed);
+    }
+
+    #[test]
+    fn recalls_the_default() {
+        let mut dat = lit();
+        dat.recall_default();
+        assert!(!dat.strips_changed);
+        dat.default_scene = Some("gone".to_string());
+        dat.recall_default();
+        assert!(!dat.strips_changed);
+        dat.default_scene = Some("dark".to_string());
+        dat.recall_default();
+        assert_eq!(dat.strips, vec![Strip(0, 0); 2]);
+        assert_eq!(dat.fade, None);
+    }
+
+    #[test]
+    fn commands() {
+        let mut dat = lit();
+        let mut run = |line: &str| command(&mut dat, &line.split(' ').collect::<Vec<_>>());
+        assert_eq!(run("list").unwrap(), "dark");
+        assert_eq!(run("save movie night").unwrap(), "saved movie night");
+        assert_eq!(
+            run("default movie night").unwrap(),
+            "movie night is the default"
+        );
+        assert_eq!(run("list").unwrap(), "dark, movie night (default)");
+        assert_eq!(
+            run("recall dark --fade 2s").unwrap(),
+            "fading into dark over 2s"
+        );
+        assert!(run("recall dark --fade soon").is_err());
+        assert_eq!(run("remove movie night").unwrap(), "removed movie night");
+        assert_eq!(run("default").unwrap(), "none");
+        assert!(run("remove movie night").is_err());
+        assert!(run("save").is_err());
+        assert_eq!(dat.fade.as_ref().unwrap().length, Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,590 @@
+use std::{
+    cmp::Reverse,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Datelike, Local};
+
+use crate::{easing::Easing, timespec, Recurrence, ScheduleUi, Strip};
+
+/// The longest delay or transition the MCU can be handed, it counts them in u32 milliseconds.
+pub const MAX_SPAN: Duration = Duration::from_millis(u32::MAX as u64);
+
+/// A keyframe with its text fields parsed, placed on the schedule's timeline.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Segment {
+    /// Counted from when the schedule was sent.
+    pub begin: Duration,
+    pub length: Duration,
+    pub target: Vec<Strip>,
+    pub easing: Easing,
+}
+
+impl Segment {
+    pub fn end(&self) -> Duration {
+        self.begin + self.length
+    }
+
+    /// Whether we're (about) done with this segment `elapsed` into the schedule.
+    ///
+    /// We're not guaranteed timing for the MCU's status updates, so long segments
+    /// get a bit of slack.
+    pub fn reached(&self, elapsed: Duration) -> bool {
+        let slack = Duration::from_millis(if self.length > Duration::from_secs(10) {
+            250
+        } else {
+            0
+        });
+        elapsed > self.end().saturating_sub(slack)
+    }
+}
+
+/// An armed schedule's place in time, used to decide who gets the lights.
+#[derive(Debug, Clone)]
+pub struct Window {
+    /// Into `SharedAppData::schedules`.
+    pub index: usize,
+    pub begin: SystemTime,
+    pub end: SystemTime,
+    pub priority: i32,
+}
+
+/// The schedule that gets the lights, and why.
+#[derive(Debug, Clone)]
+pub struct Pick {
+    pub index: usize,
+    pub why: String,
+}
+
+/// Parses every schedule, see [`ScheduleUi::timeline`].
+pub fn timelines(schedules: &mut [ScheduleUi]) -> Vec<Option<Vec<Segment>>> {
+    schedules.iter_mut().map(ScheduleUi::timeline).collect()
+}
+
+/// The windows of every armed schedule.
+pub fn windows(schedules: &[ScheduleUi], timelines: &[Option<Vec<Segment>>]) -> Vec<Window> {
+    schedules
+        .iter()
+        .zip(timelines)
+        .enumerate()
+        .filter_map(|(i, (schedule, segments))| schedule.window(i, segments.as_deref()?))
+        .collect()
+}
+
+/// Only one schedule can drive the lights at a time:
+///
+/// - Once schedules have begun, the highest priority wins, and between equal
+///   priorities the one that began last preempts the others. The losers are dropped.
+/// - Until one begins, whichever begins first is armed on the MCU, ties going to
+///   the higher priority.
+pub fn pick(windows: &[Window], now: SystemTime) -> Option<Pick> {
+    let running = windows
+        .iter()
+        .filter(|w| w.begin <= now)
+        .collect::<Vec<_>>();
+    if let Some(winner) = running.iter().max_by_key(|w| (w.priority, w.begin)) {
+        let why = if running.len() == 1 {
+            "running".to_string()
+        } else if running
+            .iter()
+            .any(|w| w.index != winner.index && w.priority == winner.priority)
+        {
+            format!("running, began last at priority {}", winner.priority)
+        } else {
+            format!("running, outranks the rest at priority {}", winner.priority)
+        };
+        return Some(Pick {
+            index: winner.index,
+            why,
+        });
+    }
+
+    let next = windows
+        .iter()
+        .min_by_key(|w| (w.begin, Reverse(w.priority)))?;
+    let why = if windows
+        .iter()
+        .any(|w| w.index != next.index && w.begin == next.begin)
+    {
+        format!(
+            "begins first at {}, outranks the rest at priority {}",
+            timespec::display(next.begin),
+            next.priority
+        )
+    } else {
+        format!("begins first at {}", timespec::display(next.begin))
+    };
+    Some(Pick {
+        index: next.index,
+        why,
+    })
+}
+
+impl ScheduleUi {
+    /// Where this schedule sits in time, if it's armed.
+    pub fn window(&self, index: usize, segments: &[Segment]) -> Option<Window> {
+        let send = self.send.filter(|_| self.enabled)?;
+        Some(Window {
+            index,
+            begin: send + segments.first()?.begin,
+            end: send + segments.last()?.end(),
+            priority: self.priority,
+        })
+    }
+
+    /// Parses `start` and every keyframe into segments, flagging the fields that don't parse.
+    pub fn timeline(&mut self) -> Option<Vec<Segment>> {
+        let mut valid = true;
+        let base = self.send.unwrap_or_else(SystemTime::now);
+        let start = self.begins_at().and_then(|at| Ok(at.duration_since(base)?));
+        let field = if self.recurrence.enabled {
+            &mut self.recurrence.time
+        } else {
+            &mut self.start
+        };
+        field.1 = start.as_ref().err().map(|_| ());
+        valid &= start.is_ok();
+        let mut at = start.unwrap_or_default();
+        let mut segments = Vec::with_capacity(self.keyframes.len());
+        self.range_error = None;
+
+        for (i, keyframe) in self.keyframes.iter_mut().enumerate() {
+            let offset = parse_field(&mut keyframe.offset, &mut valid);
+            let length = parse_field(&mut keyframe.duration, &mut valid);
+            let begin = at + offset;
+            at = begin + length;
+
+            // The MCU can't be told about anything further out than this.
+            let too_long = if begin > MAX_SPAN {
+                Some((&mut keyframe.offset, "begins"))
+            } else if length > MAX_SPAN {
+                Some((&mut keyframe.duration, "lasts"))
+            } else {
+                None
+            };
+            if let Some((field, what)) = too_long {
+                field.1 = Some(());
+                valid = false;
+                self.range_error.get_or_insert_with(|| {
+                    format!(
+                        "keyframe {i} {what} more than {} out, which is as far as the device can count",
+                        humantime::format_duration(Duration::from_secs(MAX_SPAN.as_secs()))
+                    )
+                });
+            }
+
+            segments.push(Segment {
+                begin,
+                length,
+                target: keyframe.target.clone(),
+                easing: keyframe.easing,
+            });
+        }
+
+        (valid && !segments.is_empty()).then_some(segments)
+    }
+
+    /// When the first keyframe's hold starts, relative to when the schedule
+    /// was sent (or to now, if it hasn't been yet).
+    pub fn begins_at(&self) -> Result<SystemTime> {
+        let base = self.send.unwrap_or_else(SystemTime::now);
+        if self.recurrence.enabled {
+            self.recurrence.next(base)
+        } else {
+            timespec::resolve(&self.start.0, base)
+        }
+    }
+
+    /// Where the schedule ends up, i.e. the last keyframe's target.
+    pub fn endpoint_mut(&mut self) -> Option<&mut Vec<Strip>> {
+        self.keyframes
+            .last_mut()
+            .map(|keyframe| &mut keyframe.target)
+    }
+}
+
+impl Recurrence {
+    /// The first occurrence after `after`, minding `skip_next`.
+    pub fn next(&self, after: SystemTime) -> Result<SystemTime> {
+        if !self.weekdays.contains(&true) {
+            bail!("no weekdays selected");
+        }
+        let time = timespec::parse_time(&self.time.0)?;
+
+        let after_local = DateTime::<Local>::from(after);
+        let mut skip = self.skip_next;
+        // Two weeks is always enough to find (and maybe skip) one.
+        for day in after_local.date_naive().iter_days().take(15) {
+            let weekday = day.weekday().num_days_from_monday() as usize;
+            if !self.weekdays[weekday] {
+                continue;
+            }
+            let Ok(at) = timespec::local(day.and_time(time)) else {
+                continue;
+            };
+            if at <= after_local {
+                continue;
+            }
+            if skip {
+                skip = false;
+                continue;
+            }
+            return Ok(at.into());
+        }
+        bail!("no occurrence found")
+    }
+}
+
+fn parse_field(field: &mut (String, Option<()>), valid: &mut bool) -> Duration {
+    let res = humantime::parse_duration(&field.0);
+    field.1 = res.as_ref().err().map(|_| ());
+    *valid &= res.is_ok();
+    res.unwrap_or_default()
+}
+
+/// What the strips look like `elapsed` into the schedule, having started out at `origin`.
+pub fn state_at(segments: &[Segment], origin: &[Strip], elapsed: Duration) -> Vec<Strip> {
+    let mut from = origin.to_vec();
+    for segment in segments {
+        if elapsed < segment.begin {
+            break;
+        }
+
+        if elapsed < segment.end() {
+            let progress = (elapsed - segment.begin).as_secs_f32() / segment.length.as_secs_f32();
+            let easing = segment.easing;
+            return from
+                .iter()
+                .zip(&segment.target)
+                .map(|(from, to)| {
+                    Strip(
+                        easing.interpolate(from.0, to.0, progress),
+                        easing.interpolate(from.1, to.1, progress),
+                    )
+                })
+                .collect();
+        }
+
+        from = segment.target.clone();
+    }
+    from
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        timespec::tests::{at, in_berlin},
+        Keyframe,
+    };
+
+    fn keyframe(offset: &str, duration: &str, target: u16, easing: Easing) -> Keyframe {
+        Keyframe {
+            offset: (offset.to_string(), None),
+            duration: (duration.to_string(), None),
+            target: vec![Strip(target, 0)],
+            easing,
+        }
+    }
+
+    fn schedule(keyframes: Vec<Keyframe>) -> ScheduleUi {
+        ScheduleUi {
+            start: ("1m".to_string(), None),
+            keyframes,
+            send: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            ..ScheduleUi::default()
+        }
+    }
+
+    #[test]
+    fn keyframes_follow_each_other() {
+        let mut schedule = schedule(vec![
+            keyframe("0s", "10s", 1000, Easing::Linear),
+            keyframe("5s", "20s", 0, Easing::Sigmoid),
+        ]);
+        let segments = schedule.timeline().unwrap();
+        assert_eq!(
+            segments,
+            [
+                Segment {
+                    begin: Duration::from_secs(60),
+                    length: Duration::from_secs(10),
+                    target: vec![Strip(1000, 0)],
+                    easing: Easing::Linear,
+                },
+                Segment {
+                    begin: Duration::from_secs(75),
+                    length: Duration::from_secs(20),
+                    target: vec![Strip(0, 0)],
+                    easing: Easing::Sigmoid,
+                },
+            ]
+        );
+
+        let window = schedule.window(3, &segments).unwrap();
+        let send = schedule.send.unwrap();
+        assert_eq!(window.begin, send + Duration::from_secs(60));
+        assert_eq!(window.end, send + Duration::from_secs(95));
+    }
+
+    #[test]
+    fn bad_fields_are_flagged() {
+        let mut schedule = schedule(vec![
+            keyframe("0s", "10s", 1000, Easing::Linear),
+            keyframe("soon", "20s", 0, Easing::Linear),
+        ]);
+        assert!(schedule.timeline().is_none());
+        assert_eq!(schedule.keyframes[0].offset.1, None);
+        assert_eq!(schedule.keyframes[1].offset.1, Some(()));
+        assert_eq!(schedule.keyframes[1].duration.1, None);
+
+        schedule.keyframes[1].offset.0 = "0s".into();
+        assert!(schedule.timeline().is_some());
+        assert_eq!(schedule.keyframes[1].offset.1, None);
+    }
+
+    #[test]
+    fn state_moves_through_the_segments() {
+        let segments = schedule(vec![
+            keyframe("0s", "10s", 1000, Easing::Linear),
+            keyframe("5s", "10s", 0, Easing::Linear),
+        ])
+        .timeline()
+        .unwrap();
+        let origin = [Strip(0, 500)];
+        let at = |secs| state_at(&segments, &origin, Duration::from_secs(secs))[0].clone();
+
+        assert_eq!(at(0), Strip(0, 500));
+        assert_eq!(at(65), Strip(500, 250));
+        assert_eq!(at(70), Strip(1000, 0));
+        assert_eq!(at(72), Strip(1000, 0), "holding between segments");
+        assert_eq!(at(80), Strip(500, 0));
+        assert_eq!(at(1000), Strip(0, 0));
+    }
+
+    #[test]
+    fn long_segments_get_slack() {
+        let short = Segment {
+            begin: Duration::ZERO,
+            length: Duration::from_secs(5),
+            target: vec![],
+            easing: Easing::Linear,
+        };
+        let long = Segment {
+            length: Duration::from_secs(60),
+            ..short.clone()
+        };
+        assert!(!short.reached(Duration::from_millis(4900)));
+        assert!(short.reached(Duration::from_millis(5001)));
+        assert!(!long.reached(Duration::from_millis(59_700)));
+        assert!(long.reached(Duration::from_millis(59_800)));
+    }
+
+    fn recurrence(time: &str, weekdays: [bool; 7]) -> Recurrence {
+        Recurrence {
+            enabled: true,
+            time: (time.to_string(), None),
+            weekdays,
+            skip_next: false,
+        }
+    }
+
+    const WEEKDAYS: [bool; 7] = [true, true, true, true, true, false, false];
+
+    #[test]
+    fn recurs_on_the_chosen_weekdays() {
+        in_berlin();
+        let weekdays = recurrence("06:30", WEEKDAYS);
+        // A Friday.
+        let friday = at("2023-07-14T12:00:00+02:00");
+        assert_eq!(
+            weekdays.next(friday).unwrap(),
+            at("2023-07-17T06:30:00+02:00")
+        );
+        assert_eq!(
+            weekdays.next(at("2023-07-14T06:00:00+02:00")).unwrap(),
+            at("2023-07-14T06:30:00+02:00")
+        );
+
+        let weekends = recurrence("09:00", [false, false, false, false, false, true, true]);
+        assert_eq!(
+            weekends.next(friday).unwrap(),
+            at("2023-07-15T09:00:00+02:00")
+        );
+        assert_eq!(
+            weekends.next(at("2023-07-16T10:00:00+02:00")).unwrap(),
+            at("2023-07-22T09:00:00+02:00")
+        );
+    }
+
+    #[test]
+    fn skips_the_next_one() {
+        in_berlin();
+        let skipping = Recurrence {
+            skip_next: true,
+            ..recurrence("06:30", WEEKDAYS)
+        };
+        assert_eq!(
+            skipping.next(at("2023-07-14T06:00:00+02:00")).unwrap(),
+            at("2023-07-17T06:30:00+02:00")
+        );
+
+        let mondays = Recurrence {
+            skip_next: true,
+            ..recurrence("06:30", [true, false, false, false, false, false, false])
+        };
+        assert_eq!(
+            mondays.next(at("2023-07-14T12:00:00+02:00")).unwrap(),
+            at("2023-07-24T06:30:00+02:00")
+        );
+    }
+
+    #[test]
+    fn needs_a_day_and_a_time() {
+        assert!(recurrence("06:30", [false; 7])
+            .next(SystemTime::now())
+            .is_err());
+        assert!(recurrence("half six", WEEKDAYS)
+            .next(SystemTime::now())
+            .is_err());
+    }
+
+    #[test]
+    fn keeps_local_time_across_dst() {
+        in_berlin();
+        let daily = recurrence("06:30", [true; 7]);
+        assert_eq!(
+            daily.next(at("2023-03-25T12:00:00+01:00")).unwrap(),
+            at("2023-03-26T06:30:00+02:00")
+        );
+        assert_eq!(
+            daily.next(at("2023-10-28T12:00:00+02:00")).unwrap(),
+            at("2023-10-29T06:30:00+01:00")
+        );
+
+        // 02:30 doesn't happen when the clocks go forward, so that day's skipped.
+        let nightly = recurrence("02:30", [true; 7]);
+        assert_eq!(
+            nightly.next(at("2023-03-25T12:00:00+01:00")).unwrap(),
+            at("2023-03-27T02:30:00+02:00")
+        );
+        // And when it happens twice, only the first one counts.
+        let after_first = at("2023-10-29T02:30:00+02:00");
+        assert_eq!(
+            nightly.next(after_first).unwrap(),
+            at("2023-10-30T02:30:00+01:00")
+        );
+    }
+
+    fn window(index: usize, begin: u64, priority: i32) -> Window {
+        let epoch = SystemTime::UNIX_EPOCH;
+        Window {
+            index,
+            begin: epoch + Duration::from_secs(begin),
+            end: epoch + Duration::from_secs(begin + 100),
+            priority,
+        }
+    }
+
+    fn picked(windows: &[Window], now: u64) -> Option<usize> {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(now);
+        pick(windows, now).map(|pick| pick.index)
+    }
+
+    fn why(windows: &[Window], now: u64) -> String {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(now);
+        pick(windows, now).unwrap().why
+    }
+
+    #[test]
+    fn running_schedules_go_by_priority_then_latest() {
+        let windows = [window(0, 10, 0), window(1, 20, 0), window(2, 30, 5)];
+        assert_eq!(picked(&windows, 15), Some(0));
+        // Began last at the same priority.
+        assert_eq!(picked(&windows, 25), Some(1));
+        assert_eq!(why(&windows, 25), "running, began last at priority 0");
+        // Outranks the rest.
+        assert_eq!(picked(&windows, 35), Some(2));
+        let windows = [window(0, 10, 5), window(1, 20, 0)];
+        assert_eq!(picked(&windows, 25), Some(0));
+        assert_eq!(
+            why(&windows, 25),
+            "running, outranks the rest at priority 5"
+        );
+    }
+
+    #[test]
+    fn until_then_the_first_to_begin_is_armed() {
+        assert_eq!(picked(&[], 0), None);
+        let windows = [window(0, 30, 5), window(1, 20, 0)];
+        assert_eq!(picked(&windows, 0), Some(1));
+        // Ties go to the higher priority.
+        let windows = [window(0, 20, 0), window(1, 20, 3), window(2, 20, 1)];
+        assert_eq!(picked(&windows, 0), Some(1));
+        assert!(why(&windows, 0).ends_with("outranks the rest at priority 3"));
+    }
+
+    #[test]
+    fn only_armed_schedules_have_windows() {
+        let mut schedules = vec![
+            schedule(vec![keyframe("0s", "10s", 0, Easing::Linear)]),
+            ScheduleUi {
+                enabled: false,
+                ..schedule(vec![keyframe("0s", "10s", 0, Easing::Linear)])
+            },
+            ScheduleUi {
+                send: None,
+                ..schedule(vec![keyframe("0s", "10s", 0, Easing::Linear)])
+            },
+            schedule(vec![keyframe("0s", "soon", 0, Easing::Linear)]),
+            ScheduleUi {
+                priority: 2,
+                ..schedule(vec![keyframe("0s", "10s", 0, Easing::Linear)])
+            },
+        ];
+        let timelines = timelines(&mut schedules);
+        let windows = windows(&schedules, &timelines);
+        assert_eq!(
+            windows
+                .iter()
+                .map(|w| (w.index, w.priority))
+                .collect::<Vec<_>>(),
+            [(0, 0), (4, 2)]
+        );
+    }
+
+    #[test]
+    fn timelines_stay_within_what_the_device_can_count() {
+        let mut schedule = schedule(vec![
+            keyframe("0s", "1000h", 0, Easing::Linear),
+            keyframe("0s", "1h", 0, Easing::Linear),
+        ]);
+        assert!(schedule.timeline().is_some());
+        assert_eq!(schedule.range_error, None);
+
+        // Past the 49d 17h 2m 47s a u32 of milliseconds holds.
+        schedule.keyframes[0].duration.0 = "50days".into();
+        assert!(schedule.timeline().is_none());
+        assert_eq!(schedule.keyframes[0].duration.1, Some(()));
+        assert!(schedule
+            .range_error
+            .as_ref()
+            .unwrap()
+            .starts_with("keyframe 0 lasts"));
+
+        schedule.keyframes[0].duration.0 = "49days 17h 2m".into();
+        assert!(schedule.timeline().is_none());
+        assert_eq!(schedule.keyframes[0].duration.1, None);
+        assert_eq!(schedule.keyframes[1].offset.1, Some(()));
+        assert!(schedule
+            .range_error
+            .as_ref()
+            .unwrap()
+            .starts_with("keyframe 1 begins"));
+
+        schedule.keyframes[0].duration.0 = "48days".into();
+        assert!(schedule.timeline().is_some());
+        assert_eq!(schedule.range_error, None);
+    }
+}
@@ -0,0 +1,503 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use eframe::egui::{DragValue, Ui};
+use rhai::{
+    module_resolvers::DummyModuleResolver, Array, CallFnOptions, Dynamic, Engine, EvalAltResult,
+    Map, Scope, AST, FLOAT, INT,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{controller::Controller, SharedAppData, Strip};
+
+/// How long a script gets to come up with each tick.
+const BUDGET: Duration = Duration::from_millis(2);
+/// How often the file's checked for changes.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Runs a [Rhai](https://rhai.rs) script from the `scripts` folder in the config directory.
+///
+/// The script defines `fn tick(ctx)`, with `ctx` holding
+/// - `t`, seconds since the controller was picked,
+/// - `time`, seconds since the Unix epoch,
+/// - `strips`, as `[[cold, warm], ...]` from 0 to 65535,
+/// - `relay`, whether the relay's on,
+/// - `params`, whatever's set in the UI,
+///
+/// and returns the strips in the same shape, or `()` to leave them be. `this` is a map that
+/// sticks around between ticks. An optional `fn params()` returns a map of parameters and
+/// their defaults.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Script {
+    /// The file name, without the `.rhai`.
+    pub file: String,
+    pub params: BTreeMap<String, f64>,
+    #[serde(skip)]
+    #[serde(default = "SystemTime::now")]
+    started_at: SystemTime,
+    #[serde(skip)]
+    loaded: Option<Loaded>,
+}
+
+/// A script compiled and ready to go.
+struct Loaded {
+    engine: Engine,
+    /// When the running tick has to give up by.
+    deadline: Arc<Mutex<Instant>>,
+    /// `None` if it didn't compile.
+    ast: Option<AST>,
+    modified: Option<SystemTime>,
+    checked_at: Option<Instant>,
+    state: Dynamic,
+    status: String,
+}
+
+impl Script {
+    pub fn new(file: String) -> Self {
+        Script {
+            file,
+            ..Default::default()
+        }
+    }
+
+    pub fn dir() -> Result<PathBuf> {
+        Ok(SharedAppData::config_dir()?.join("scripts"))
+    }
+
+    /// Compiles the script if it's new or changed.
+    fn reload(&mut self) {
+        let loaded = self.loaded.get_or_insert_with(Loaded::new);
+        if loaded
+            .checked_at
+            .is_some_and(|at| at.elapsed() < CHECK_INTERVAL)
+        {
+            return;
+        }
+        let first = loaded.checked_at.is_none();
+        loaded.checked_at = Some(Instant::now());
+
+        let path = Self::dir().map(|dir| dir.join(format!("{}.rhai", self.file)));
+        let modified = path
+            .as_ref()
+            .ok()
+            .and_then(|path| fs::metadata(path).and_then(|m| m.modified()).ok());
+        if !first && loaded.modified == modified {
+            return;
+        }
+        loaded.modified = modified;
+        loaded.state = Map::new().into();
+
+        let source = match path.and_then(|path| Ok(fs::read_to_string(path)?)) {
+            Ok(source) => source,
+            Err(err) => {
+                loaded.ast = None;
+                loaded.status = format!("couldn't read {}.rhai: {err}", self.file);
+                return;
+            }
+        };
+        match loaded.engine.compile(source) {
+            Ok(ast) => {
+                loaded.status = "running".into();
+                // Fill in parameters it has that we don't yet.
+                *loaded.deadline.lock().unwrap() = Instant::now() + BUDGET;
+                let options = CallFnOptions::new().eval_ast(false);
+                if let Ok(defaults) = loaded.engine.call_fn_with_options::<Map>(
+                    options,
+                    &mut Scope::new(),
+                    &ast,
+                    "params",
+                    (),
+                ) {
+                    for (name, value) in defaults {
+                        if let Some(value) = number(&value) {
+                            self.params.entry(name.to_string()).or_insert(value);
+                        }
+                    }
+                }
+                loaded.ast = Some(ast);
+            }
+            Err(err) => {
+                loaded.ast = None;
+                loaded.status = format!("doesn't compile: {err}");
+            }
+        }
+    }
+}
+
+impl Loaded {
+    fn new() -> Self {
+        let mut engine = Engine::new();
+        // No files, and nothing running away with the update loop.
+        engine.set_module_resolver(DummyModuleResolver::new());
+        engine.disable_symbol("eval");
+        engine.set_max_operations(1_000_000);
+        engine.set_max_call_levels(32);
+        engine.set_max_string_size(4096);
+        engine.set_max_array_size(4096);
+        engine.set_max_map_size(4096);
+        let deadline = Arc::new(Mutex::new(Instant::now()));
+        let budget = deadline.clone();
+        engine.on_progress(move |ops| {
+            (ops % 256 == 0 && Instant::now() > *budget.lock().unwrap())
+                .then(|| "ran out of time".into())
+        });
+
+        Loaded {
+            engine,
+            deadline,
+            ast: None,
+            modified: None,
+            checked_at: None,
+            state: Map::new().into(),
+            status: String::new(),
+        }
+    }
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        Script {
+            file: String::new(),
+            params: BTreeMap::new(),
+            started_at: SystemTime::now(),
+            loaded: None,
+        }
+    }
+}
+
+// Not what's loaded though, that gets loaded again.
+impl Clone for Script {
+    fn clone(&self) -> Self {
+        Script {
+            file: self.file.clone(),
+            params: self.params.clone(),
+            started_at: self.started_at,
+            loaded: None,
+        }
+    }
+}
+
+/// [`available`], looked up again at most every [`CHECK_INTERVAL`] since the UI wants it
+/// every frame.
+#[derive(Default)]
+pub struct Available {
+    scripts: Vec<String>,
+    checked_at: Option<Instant>,
+}
+
+impl Available {
+    pub fn get(&mut self) -> &[String] {
+        if self
+            .checked_at
+            .is_none_or(|at| at.elapsed() >= CHECK_INTERVAL)
+        {
+            self.scripts = available();
+            self.checked_at = Some(Instant::now());
+        }
+        &self.scripts
+    }
+}
+
+/// The scripts in [`Script::dir`], by file name without the `.rhai`.
+pub fn available() -> Vec<String> {
+    let Ok(entries) = Script::dir().and_then(|dir| Ok(fs::read_dir(dir)?)) else {
+        return vec![];
+    };
+    let mut files = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter_map(|name| Some(name.strip_suffix(".rhai")?.to_string()))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+fn number(value: &Dynamic) -> Option<f64> {
+    value
+        .as_float()
+        .ok()
+        .or_else(|| value.as_int().ok().map(|int| int as FLOAT))
+}
+
+/// Reads back `[[cold, warm], ...]`, clamping each value into range.
+fn read_strips(value: Dynamic) -> Result<Vec<Strip>, String> {
+    let channel = |value: &Dynamic| {
+        number(value)
+            .map(|value| value.clamp(0.0, f64::from(u16::MAX)) as u16)
+            .ok_or_else(|| format!("expected a number, got {}", value.type_name()))
+    };
+    value
+        .into_array()
+        .map_err(|ty| format!("tick returned {ty}, not an array"))?
+        .iter()
+        .map(
+            |strip| match strip.read_lock::<Array>().as_deref().map(Vec::as_slice) {
+                Some([cold, warm]) => Ok(Strip(channel(cold)?, channel(warm)?)),
+                _ => Err("each strip should be [cold, warm]".into()),
+            },
+        )
+        .collect()
+}
+
+impl Controller for Script {
+    fn name(&self) -> &'static str {
+        "Script"
+    }
+
+    fn label(&self) -> String {
+        self.file.clone()
+    }
+
+    fn output(&mut self, at: SystemTime, strips: &[Strip], relay: bool) -> Option<Vec<Strip>> {
+        self.reload();
+        let loaded = self.loaded.as_mut()?;
+        let ast = loaded.ast.as_ref()?;
+
+        let seconds = |since| at.duration_since(since).unwrap_or_default().as_secs_f64();
+        let mut ctx = Map::new();
+        ctx.insert("t".into(), seconds(self.started_at).into());
+        ctx.insert("time".into(), seconds(UNIX_EPOCH).into());
+        let array = strips
+            .iter()
+            .map(|strip| {
+                Dynamic::from_array(vec![INT::from(strip.0).into(), INT::from(strip.1).into()])
+            })
+            .collect::<Array>();
+        ctx.insert("strips".into(), array.into());
+        ctx.insert("relay".into(), relay.into());
+        let params = self
+            .params
+            .iter()
+            .map(|(name, value)| (name.into(), (*value).into()))
+            .collect::<Map>();
+        ctx.insert("params".into(), params.into());
+
+        *loaded.deadline.lock().unwrap() = Instant::now() + BUDGET;
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut loaded.state);
+        let result = loaded
+            .engine
+            .call_fn_with_options::<Dynamic>(options, &mut Scope::new(), ast, "tick", (ctx,))
+            .map_err(|err| match *err {
+                EvalAltResult::ErrorTerminated(..) => format!("took longer than {BUDGET:?}"),
+                err => err.to_string(),
+            })
+            .and_then(|value| {
+                if value.is_unit() {
+                    Ok(None)
+                } else {
+                    read_strips(value).map(Some)
+                }
+            });
+        let output = match result {
+            Ok(output) => {
+                loaded.status = "running".into();
+                output?
+            }
+            Err(err) => {
+                loaded.status = err;
+                return None;
+            }
+        };
+
+        let mut strips = strips.to_vec();
+        for (strip, value) in strips.iter_mut().zip(output) {
+            *strip = value;
+        }
+        Some(strips)
+    }
+
+    fn realtime(&self) -> bool {
+        true
+    }
+
+    fn params(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("file", self.file.clone()),
+            ("params", format!("{:?}", self.params)),
+        ]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.group(|ui| {
+            match Self::dir() {
+                Ok(dir) => ui.label(format!(
+                    "Script {}",
+                    dir.join(format!("{}.rhai", self.file)).display()
+                )),
+                Err(err) => ui.label(format!("Script: {err}")),
+            };
+            if let Some(loaded) = &self.loaded {
+                ui.label(&loaded.status);
+            }
+            for (name, value) in &mut self.params {
+                ui.horizontal(|ui| {
+                    ui.add(DragValue::new(value).speed(0.01));
+                    ui.label(name);
+                });
+            }
+        });
+    }
+
+    fn save(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::config::tests::scratch_config;
+
+    /// Writes `scripts/{file}.rhai` and picks it.
+    fn script(file: &str, source: &str) -> Script {
+        scratch_config();
+        let dir = Script::dir().unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(format!("{file}.rhai")), source).unwrap();
+        Script::new(file.to_string())
+    }
+
+    fn tick(script: &mut Script, strips: &[Strip]) -> Option<Vec<Strip>> {
+        script.output(SystemTime::now(), strips, false)
+    }
+
+    fn status(script: &Script) -> &str {
+        &script.loaded.as_ref().unwrap().status
+    }
+
+    #[test]
+    fn ticks_with_state_and_params() {
+        let mut script = script(
+            "counter",
+            r#"
+                fn params() { #{ step: 100, level: 7, label: "not a number" } }
+                fn tick(ctx) {
+                    if "n" in this { this.n += 1 } else { this.n = 1 }
+                    let cold = ctx.strips[0][0] + this.n * ctx.params.step;
+                    [[cold, ctx.params.level]]
+                }
+            "#,
+        );
+        script.params.insert("level".into(), 1.0);
+        let strips = [Strip(1000, 0), Strip(5, 5)];
+        assert_eq!(
+            tick(&mut script, &strips),
+            Some(vec![Strip(1100, 1), Strip(5, 5)])
+        );
+        assert_eq!(
+            tick(&mut script, &strips),
+            Some(vec![Strip(1200, 1), Strip(5, 5)])
+        );
+        assert_eq!(status(&script), "running");
+        // Only the numbers it didn't have yet.
+        assert_eq!(
+            script.params,
+            BTreeMap::from([("level".into(), 1.0), ("step".into(), 100.0)])
+        );
+    }
+
+    #[test]
+    fn reloads_when_the_file_changes() {
+        let mut script = script("reloading", "fn tick(ctx) { [[1, 1]] }");
+        assert_eq!(tick(&mut script, &[Strip(0, 0)]), Some(vec![Strip(1, 1)]));
+
+        let path = Script::dir().unwrap().join("reloading.rhai");
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let rewrite = |source: &str, modified: SystemTime| {
+            fs::write(&path, source).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+        let due = |script: &mut Script| {
+            script.loaded.as_mut().unwrap().checked_at = Instant::now().checked_sub(CHECK_INTERVAL);
+        };
+
+        // Not looked at again until CHECK_INTERVAL is up, and only if it's been modified.
+        rewrite("fn tick(ctx) { [[2, 2]] }", modified);
+        assert_eq!(tick(&mut script, &[Strip(0, 0)]), Some(vec![Strip(1, 1)]));
+        due(&mut script);
+        assert_eq!(tick(&mut script, &[Strip(0, 0)]), Some(vec![Strip(1, 1)]));
+        rewrite(
+            "fn tick(ctx) { [[3, 3]] }",
+            modified + Duration::from_secs(1),
+        );
+        assert_eq!(tick(&mut script, &[Strip(0, 0)]), Some(vec![Strip(1, 1)]));
+        due(&mut script);
+        assert_eq!(tick(&mut script, &[Strip(0, 0)]), Some(vec![Strip(3, 3)]));
+
+        fs::remove_file(&path).unwrap();
+        due(&mut script);
+        assert_eq!(tick(&mut script, &[Strip(0, 0)]), None);
+        assert!(status(&script).starts_with("couldn't read reloading.rhai"));
+    }
+
+    #[test]
+    fn stays_in_the_sandbox() {
+        let mut evil = script("eval", r#"fn tick(ctx) { eval("[[1, 1]]") }"#);
+        assert_eq!(tick(&mut evil, &[Strip(0, 0)]), None);
+        assert!(
+            status(&evil).starts_with("doesn't compile"),
+            "{}",
+            status(&evil)
+        );
+
+        let mut importer = script(
+            "importer",
+            r#"fn tick(ctx) { import "counter" as counter; [[1, 1]] }"#,
+        );
+        assert_eq!(tick(&mut importer, &[Strip(0, 0)]), None);
+        assert!(
+            status(&importer).contains("counter"),
+            "{}",
+            status(&importer)
+        );
+    }
+
+    #[test]
+    fn gives_up_on_slow_ticks() {
+        let mut script = script("spinning", "fn tick(ctx) { loop {} }");
+        let started = Instant::now();
+        assert_eq!(tick(&mut script, &[Strip(0, 0)]), None);
+        assert_eq!(status(&script), "took longer than 2ms");
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn reads_strips_back() {
+        let engine = Engine::new();
+        let eval = |source: &str| read_strips(engine.eval::<Dynamic>(source).unwrap());
+        assert_eq!(
+            eval("[[-5, 70000.5], [12.7, 3]]"),
+            Ok(vec![Strip(0, u16::MAX), Strip(12, 3)])
+        );
+        assert_eq!(eval("[]"), Ok(vec![]));
+        assert_eq!(eval("5"), Err("tick returned i64, not an array".into()));
+        assert_eq!(
+            eval("[[1]]"),
+            Err("each strip should be [cold, warm]".into())
+        );
+        assert_eq!(eval("[5]"), Err("each strip should be [cold, warm]".into()));
+        assert_eq!(
+            eval(r#"[[1, "2"]]"#),
+            Err("expected a number, got string".into())
+        );
+    }
+}
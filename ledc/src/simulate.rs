@@ -0,0 +1,404 @@
+use std::{
+    fs,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Local, SecondsFormat};
+
+use crate::{calendar, schedule, timespec, Action, SharedAppData, Strip};
+
+/// What the lights are doing at one point of a simulation.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub at: SystemTime,
+    pub strips: Vec<Strip>,
+    /// Who's in charge: the controller, or the schedule driving the lights.
+    pub source: String,
+}
+
+/// Runs the controller, schedules, triggers and calendars against a virtual clock,
+/// sampling every `step` from `from` up to `to`.
+///
+/// This doesn't touch the MCU, but does model it: a realtime controller (like a wave) keeps
+/// interrupting the MCU's transitions, so they lerp from wherever it's at.
+///
+/// [`Controller::live`] controllers (like audio) aren't run at all, they'd go and listen
+/// for real. The strips stay as they are instead, and the samples say so.
+///
+/// [`Controller::live`]: crate::controller::Controller::live
+pub fn simulate(
+    dat: &SharedAppData,
+    from: SystemTime,
+    to: SystemTime,
+    step: Duration,
+) -> Result<Vec<Sample>> {
+    if step.is_zero() {
+        bail!("step has to be longer than zero");
+    }
+    let mut dat = dat.clone();
+    let events = events(&dat, from, to);
+
+    let mut samples = vec![];
+    let mut next_event = 0;
+    let mut at = from;
+    while at <= to {
+        while let Some((when, action)) = events.get(next_event).filter(|(when, _)| *when <= at) {
+            action.run(&mut dat, *when);
+            next_event += 1;
+        }
+
+        let mut source = dat.controller.name().to_lowercase();
+        if dat.controller.live() {
+            source += " (live, not simulated)";
+        } else if let Some(strips) = dat.controller.output(at, &dat.strips, dat.relay_enabled) {
+            dat.strips = strips;
+        }
+
+        let sample = match scheduled(&mut dat, at) {
+            Some((name, strips)) => Sample {
+                at,
+                strips: dat.capped(&strips),
+                source: format!("schedule {name}"),
+            },
+            None => Sample {
+                at,
+                strips: dat.capped(&dat.strips),
+                source,
+            },
+        };
+        samples.push(sample);
+        at += step;
+    }
+    Ok(samples)
+}
+
+/// Every trigger and calendar rule firing in `from..=to`, in order.
+fn events(dat: &SharedAppData, from: SystemTime, to: SystemTime) -> Vec<(SystemTime, Action)> {
+    let mut events = vec![];
+    for trigger in dat.triggers.iter().filter(|trigger| trigger.enabled) {
+        let mut after = from;
+        'more: while let Ok(times) = trigger.upcoming(after, 256) {
+            for &at in &times {
+                if at > to {
+                    break 'more;
+                }
+                events.push((at, trigger.action.clone()));
+            }
+            match times.last() {
+                Some(&last) => after = last,
+                None => break,
+            }
+        }
+    }
+
+    // Give the lead times room to reach back into the range.
+    let margin = Duration::from_secs(86_400);
+    for calendar in dat.calendars.iter().filter(|calendar| calendar.enabled) {
+        let Ok(ics) = fs::read_to_string(&calendar.path) else {
+            continue;
+        };
+        let occurrences = calendar::parse(&ics, from - margin, to + margin).occurrences;
+        for rule in &calendar.rules {
+            for at in rule.fire_times(&occurrences).unwrap_or_default() {
+                if from < at && at <= to {
+                    events.push((at, rule.action.clone()));
+                }
+            }
+        }
+    }
+
+    events.sort_by_key(|(at, _)| *at);
+    events
+}
+
+/// What the schedule that has the lights at `now` makes of them, like `update_thread` would.
+fn scheduled(dat: &mut SharedAppData, now: SystemTime) -> Option<(String, Vec<Strip>)> {
+    for schedule in &mut dat.schedules {
+        if schedule.enabled && schedule.recurrence.enabled && schedule.send.is_none() {
+            schedule.send = Some(now);
+        }
+    }
+
+    let timelines = schedule::timelines(&mut dat.schedules);
+    let windows = schedule::windows(&dat.schedules, &timelines);
+    let pick = schedule::pick(&windows, now)?.index;
+    for (i, schedule) in dat.schedules.iter_mut().enumerate() {
+        if i != pick && windows.iter().any(|w| w.index == i && w.begin <= now) {
+            schedule.send = None;
+        }
+    }
+
+    let segments = timelines[pick].as_ref()?;
+    let schedule = &mut dat.schedules[pick];
+    let elapsed = now.duration_since(schedule.send?).ok()?;
+    if elapsed < segments.first()?.begin {
+        return None;
+    }
+    if elapsed >= segments.last()?.end() {
+        if schedule.swap_on_stop {
+            let prev = dat.strips.clone();
+            if let Some(endpoint) = schedule.endpoint_mut() {
+                dat.strips = std::mem::replace(endpoint, prev);
+            }
+        }
+        schedule.send = None;
+        schedule.recurrence.skip_next = false;
+        return None;
+    }
+
+    let strips = if dat.controller.realtime() {
+        // Only the transitions themselves get through, lerping from whatever's being streamed.
+        let current = segments
+            .iter()
+            .find(|segment| segment.begin <= elapsed && elapsed < segment.end())?;
+        schedule::state_at(std::slice::from_ref(current), &dat.strips, elapsed)
+    } else {
+        schedule::state_at(segments, &dat.strips, elapsed)
+    };
+    Some((schedule.name.clone(), strips))
+}
+
+/// `strip0_cold`, `strip0_warm`, ... for as many strips as there are.
+pub fn channels(strips: usize) -> Vec<String> {
+    (0..strips)
+        .flat_map(|i| [format!("strip{i}_cold"), format!("strip{i}_warm")])
+        .collect()
+}
+
+fn timestamp(at: SystemTime) -> String {
+    DateTime::<Local>::from(at).to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+pub fn to_csv(samples: &[Sample]) -> String {
+    let strips = samples.first().map_or(0, |sample| sample.strips.len());
+    let mut out = format!("time,source,{}\n", channels(strips).join(","));
+    for sample in samples {
+        let values = sample
+            .strips
+            .iter()
+            .flat_map(|strip| [strip.0.to_string(), strip.1.to_string()])
+            .collect::<Vec<_>>();
+        out += &format!(
+            "{},\"{}\",{}\n",
+            timestamp(sample.at),
+            sample.source.replace('"', "\"\""),
+            values.join(",")
+        );
+    }
+    out
+}
+
+pub fn to_json(samples: &[Sample]) -> Result<String> {
+    let rows = samples
+        .iter()
+        .map(|sample| {
+            let mut row = serde_json::Map::new();
+            row.insert("time".into(), timestamp(sample.at).into());
+            row.insert("source".into(), sample.source.clone().into());
+            let values = sample.strips.iter().flat_map(|strip| [strip.0, strip.1]);
+            for (channel, value) in channels(sample.strips.len()).into_iter().zip(values) {
+                row.insert(channel, value.into());
+            }
+            row.into()
+        })
+        .collect::<Vec<serde_json::Value>>();
+    Ok(serde_json::to_string_pretty(&rows)?)
+}
+
+/// `ledc simulate [--from SPEC] [--to SPEC] [--step DURATION] [--format csv|json]`
+///
+/// `--from` is relative to now and `--to` to `--from`, both taking whatever a schedule
+/// start does (see [`timespec::resolve`]).
+pub fn cli(args: &[String]) -> Result<()> {
+    let (mut from, mut to, mut step, mut format) = ("0s", "1d", "1m", "csv");
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| anyhow!("{flag} needs a value"))?
+            .as_str();
+        match flag.as_str() {
+            "--from" => from = value,
+            "--to" => to = value,
+            "--step" => step = value,
+            "--format" => format = value,
+            _ => bail!("unknown flag {flag:?}"),
+        }
+    }
+
+    let from = timespec::resolve(from, SystemTime::now())?;
+    let to = timespec::resolve(to, from)?;
+    let step = humantime::parse_duration(step)?;
+    let dat = SharedAppData::read_config()?.unwrap_or_default();
+    let samples = simulate(&dat, from, to, step)?;
+    match format {
+        "csv" => print!("{}", to_csv(&samples)),
+        "json" => println!("{}", to_json(&samples)?),
+        _ => bail!("unknown format {format:?}, try csv or json"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audio::Audio,
+        timespec::tests::{at, in_berlin},
+        Keyframe, ScheduleUi, Trigger,
+    };
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn keyframe(offset: &str, duration: &str, target: u16) -> Keyframe {
+        Keyframe {
+            offset: (offset.to_string(), None),
+            duration: (duration.to_string(), None),
+            target: vec![Strip(target, 0); 2],
+            ..Keyframe::default()
+        }
+    }
+
+    /// The cold channel of the first strip, and who had it, at every sample.
+    fn cold(samples: &[Sample]) -> Vec<(u16, &str)> {
+        samples
+            .iter()
+            .map(|sample| (sample.strips[0].0, sample.source.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn follows_the_keyframes() {
+        let from = at("2023-07-14T12:00:00Z");
+        let mut dat = SharedAppData::new();
+        dat.schedules = vec![ScheduleUi {
+            name: "Fade".into(),
+            start: ("10m".into(), None),
+            keyframes: vec![keyframe("0s", "10m", 1000), keyframe("5m", "10m", 0)],
+            send: Some(from),
+            ..ScheduleUi::default()
+        }];
+
+        let samples = simulate(&dat, from, from + 40 * MINUTE, 5 * MINUTE).unwrap();
+        assert_eq!(
+            cold(&samples),
+            [
+                (0, "manual"),
+                (0, "manual"),
+                (0, "schedule Fade"),
+                (500, "schedule Fade"),
+                (1000, "schedule Fade"),
+                (1000, "schedule Fade"),
+                (500, "schedule Fade"),
+                (0, "manual"),
+                (0, "manual"),
+            ]
+        );
+        assert_eq!(samples[3].at, from + 15 * MINUTE);
+    }
+
+    #[test]
+    fn recurs_every_morning() {
+        in_berlin();
+        let mut dat = SharedAppData::new();
+        let mut schedule = ScheduleUi {
+            name: "Wake up".into(),
+            keyframes: vec![keyframe("0s", "30m", 1000)],
+            ..ScheduleUi::default()
+        };
+        schedule.recurrence.enabled = true;
+        schedule.recurrence.time.0 = "07:00".into();
+        schedule.recurrence.weekdays = [true; 7];
+        dat.schedules = vec![schedule];
+
+        let from = at("2023-07-14T06:00:00+02:00");
+        let samples = simulate(&dat, from, from + 26 * 60 * MINUTE, 15 * MINUTE).unwrap();
+        let sample = |at_: &str| {
+            let sample = samples.iter().find(|s| s.at == at(at_)).unwrap();
+            (sample.strips[0].0, sample.source.as_str())
+        };
+        assert_eq!(sample("2023-07-14T06:45:00+02:00"), (0, "manual"));
+        assert_eq!(
+            sample("2023-07-14T07:15:00+02:00"),
+            (500, "schedule Wake up")
+        );
+        // Back to the strips when it's done, and round again the next day.
+        assert_eq!(sample("2023-07-14T12:00:00+02:00"), (0, "manual"));
+        assert_eq!(
+            sample("2023-07-15T07:15:00+02:00"),
+            (500, "schedule Wake up")
+        );
+    }
+
+    #[test]
+    fn runs_the_triggers() {
+        let from = at("2023-07-14T08:00:00Z");
+        let mut dat = SharedAppData::new();
+        dat.schedules.clear();
+        dat.triggers = vec![Trigger {
+            enabled: true,
+            cron: ("0 0 9 * * *".into(), None),
+            timezone: ("UTC".into(), None),
+            action: Action::SetStrips(vec![Strip(700, 0); 2]),
+            ..Trigger::default()
+        }];
+        let samples = simulate(&dat, from, from + 120 * MINUTE, 30 * MINUTE).unwrap();
+        let values = cold(&samples).into_iter().map(|(value, _)| value);
+        assert_eq!(values.collect::<Vec<_>>(), [0, 0, 700, 700, 700]);
+        // The real thing's untouched.
+        assert_eq!(dat.strips[0], Strip(0, 0));
+        assert_eq!(
+            dat.triggers[0].action,
+            Action::SetStrips(vec![Strip(700, 0); 2])
+        );
+    }
+
+    #[test]
+    fn leaves_live_controllers_out() {
+        let from = at("2023-07-14T12:00:00Z");
+        let mut dat = SharedAppData::new();
+        dat.schedules.clear();
+        dat.strips = vec![Strip(300, 200); 2];
+        dat.controller = Box::new(Audio::default());
+        let samples = simulate(&dat, from, from + MINUTE, MINUTE).unwrap();
+        assert_eq!(cold(&samples), [(300, "audio (live, not simulated)"); 2]);
+    }
+
+    #[test]
+    fn exports() {
+        in_berlin();
+        let samples = [Sample {
+            at: at("2023-07-14T12:00:00Z"),
+            strips: vec![Strip(1, 2)],
+            source: "schedule \"Fade\"".into(),
+        }];
+        assert_eq!(
+            to_csv(&samples),
+            "time,source,strip0_cold,strip0_warm\n\
+             2023-07-14T14:00:00+02:00,\"schedule \"\"Fade\"\"\",1,2\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&to_json(&samples).unwrap()).unwrap();
+        assert_eq!(json[0]["strip0_warm"], 2);
+        assert_eq!(json[0]["source"], "schedule \"Fade\"");
+    }
+
+    #[test]
+    fn holds_everything_to_the_caps() {
+        let from = at("2023-07-14T12:00:00Z");
+        let mut dat = SharedAppData::new();
+        dat.strips = vec![Strip(300, 60000); 2];
+        dat.caps = Strip(200, 40000);
+        dat.schedules = vec![ScheduleUi {
+            start: ("1m".into(), None),
+            keyframes: vec![keyframe("0s", "2m", 1000)],
+            send: Some(from),
+            ..ScheduleUi::default()
+        }];
+        let samples = simulate(&dat, from, from + 2 * MINUTE, MINUTE).unwrap();
+        assert_eq!(samples[0].strips, vec![Strip(200, 40000); 2]);
+        // Halfway through, on the way to Strip(1000, 0).
+        assert_eq!(samples[2].strips, vec![Strip(200, 30000); 2]);
+        assert_eq!(samples[2].source, "schedule Schedule");
+    }
+}
@@ -0,0 +1,296 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use eframe::egui::{DragValue, Slider, Ui};
+use serde::{Deserialize, Serialize};
+
+use crate::{controller::Controller, timespec, ui, Strip};
+
+/// Where the sun is considered to rise and set, accounting for refraction and its radius.
+pub const HORIZON: f64 = -0.833;
+/// Bottom of civil twilight.
+pub const CIVIL_TWILIGHT: f64 = -6.0;
+
+/// The sun's elevation above the horizon in degrees, good to a fraction of a degree.
+///
+/// This is the usual low-precision almanac formula, no network or tables needed.
+pub fn elevation(at: SystemTime, latitude: f64, longitude: f64) -> f64 {
+    // Days since J2000.0 (2000-01-01 12:00 UTC).
+    let unix = match at.duration_since(UNIX_EPOCH) {
+        Ok(dur) => dur.as_secs_f64(),
+        Err(err) => -err.duration().as_secs_f64(),
+    };
+    let d = unix / 86_400.0 - 10_957.5;
+
+    let mean_anomaly = (357.529 + 0.985_600_28 * d).to_radians();
+    let mean_longitude = 280.459 + 0.985_647_36 * d;
+    let ecliptic_longitude =
+        (mean_longitude + 1.915 * mean_anomaly.sin() + 0.020 * (2.0 * mean_anomaly).sin())
+            .to_radians();
+    let obliquity = (23.439 - 0.000_000_36 * d).to_radians();
+
+    let right_ascension =
+        (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+
+    let sidereal_hours = 18.697_374_558 + 24.065_709_824_419_08 * d;
+    let hour_angle = (sidereal_hours * 15.0 + longitude).to_radians() - right_ascension;
+
+    let latitude = latitude.to_radians();
+    (latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos())
+        .asin()
+        .to_degrees()
+}
+
+/// The next time (within two days) the sun crosses `threshold` degrees, and whether it's rising.
+///
+/// `None` near the poles, where it may not cross at all.
+pub fn next_crossing(
+    from: SystemTime,
+    latitude: f64,
+    longitude: f64,
+    threshold: f64,
+) -> Option<(SystemTime, bool)> {
+    let step = Duration::from_secs(10 * 60);
+    let above = |at| elevation(at, latitude, longitude) > threshold;
+
+    let mut prev = from;
+    let was_above = above(prev);
+    while prev < from + Duration::from_secs(2 * 86_400) {
+        let next = prev + step;
+        if above(next) != was_above {
+            // Narrow it down to the second.
+            let (mut lo, mut hi) = (prev, next);
+            while hi.duration_since(lo).ok()? > Duration::from_secs(1) {
+                let mid = lo + hi.duration_since(lo).ok()? / 2;
+                if above(mid) == was_above {
+                    lo = mid;
+                } else {
+                    hi = mid;
+                }
+            }
+            return Some((hi, !was_above));
+        }
+        prev = next;
+    }
+    None
+}
+
+/// How far into the day we are, 0 being night and 1 being day, with
+/// the transition spread between the two elevations.
+pub fn daylight(elevation: f64, night_elevation: f64, day_elevation: f64) -> f32 {
+    if day_elevation <= night_elevation {
+        return if elevation >= day_elevation { 1.0 } else { 0.0 };
+    }
+    let t = ((elevation - night_elevation) / (day_elevation - night_elevation)).clamp(0.0, 1.0);
+    // smoothstep, so it eases in and out of the transition
+    (t * t * (3.0 - 2.0 * t)) as f32
+}
+
+/// Blends the night and day targets for the given amount of daylight.
+pub fn blend(night: &[Strip], day: &[Strip], daylight: f32) -> Vec<Strip> {
+    let lerp = |a: u16, b: u16| (f32::from(a) + (f32::from(b) - f32::from(a)) * daylight) as u16;
+    night
+        .iter()
+        .zip(day)
+        .map(|(night, day)| Strip(lerp(night.0, day.0), lerp(night.1, day.1)))
+        .collect()
+}
+
+/// Follows the sun, like redshift.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct Solar {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub day: Vec<Strip>,
+    pub night: Vec<Strip>,
+    /// Sun elevations (degrees) below which it's fully night, and above which it's fully day.
+    pub night_elevation: f64,
+    pub day_elevation: f64,
+    /// Set by touching the sliders, holds until the sun moves on to the other phase.
+    pub overridden: bool,
+    #[serde(skip)]
+    pub was_day: Option<bool>,
+}
+
+impl Default for Solar {
+    fn default() -> Self {
+        Solar {
+            latitude: 0.0,
+            longitude: 0.0,
+            day: vec![Strip(u16::MAX, u16::MAX / 2); 2],
+            night: vec![Strip(0, u16::MAX / 4); 2],
+            night_elevation: CIVIL_TWILIGHT,
+            day_elevation: 3.0,
+            overridden: false,
+            was_day: None,
+        }
+    }
+}
+
+impl Controller for Solar {
+    fn name(&self) -> &'static str {
+        "Solar"
+    }
+
+    /// Where the sun wants the strips to be at `at`, unless a manual change is holding it off.
+    fn output(&mut self, at: SystemTime, _strips: &[Strip], _relay: bool) -> Option<Vec<Strip>> {
+        let daylight = daylight(
+            elevation(at, self.latitude, self.longitude),
+            self.night_elevation,
+            self.day_elevation,
+        );
+
+        // The sun moved on, so whatever the user set by hand is stale now.
+        let is_day = daylight >= 0.5;
+        if self.was_day.is_some_and(|was_day| was_day != is_day) {
+            self.overridden = false;
+        }
+        self.was_day = Some(is_day);
+
+        (!self.overridden).then(|| blend(&self.night, &self.day, daylight))
+    }
+
+    fn params(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("latitude", self.latitude.to_string()),
+            ("longitude", self.longitude.to_string()),
+            ("night below", format!("{}°", self.night_elevation)),
+            ("day above", format!("{}°", self.day_elevation)),
+            ("overridden", self.overridden.to_string()),
+        ]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.group(|ui| {
+            ui.label("Solar controls");
+            ui.horizontal(|ui| {
+                ui.add(
+                    DragValue::new(&mut self.latitude)
+                        .clamp_range(-90.0..=90.0)
+                        .speed(0.1)
+                        .prefix("lat "),
+                );
+                ui.add(
+                    DragValue::new(&mut self.longitude)
+                        .clamp_range(-180.0..=180.0)
+                        .speed(0.1)
+                        .prefix("lon "),
+                );
+            });
+            ui.add(Slider::new(&mut self.night_elevation, -18.0..=10.0).text("night below (°)"));
+            ui.add(Slider::new(&mut self.day_elevation, -18.0..=10.0).text("day above (°)"));
+
+            let now = SystemTime::now();
+            ui.label(format!(
+                "sun at {:.1}°",
+                elevation(now, self.latitude, self.longitude)
+            ));
+            for (threshold, rise, set) in [
+                (HORIZON, "sunrise", "sunset"),
+                (CIVIL_TWILIGHT, "dawn", "dusk"),
+            ] {
+                match next_crossing(now, self.latitude, self.longitude, threshold) {
+                    Some((at, rising)) => ui.label(format!(
+                        "next {}: {}",
+                        if rising { rise } else { set },
+                        timespec::display(at)
+                    )),
+                    None => ui.label(format!("no {rise} or {set} in the next two days")),
+                };
+            }
+
+            if self.overridden {
+                ui.horizontal(|ui| {
+                    ui.label("Manually overridden until the sun moves on");
+                    if ui.button("Resume").clicked() {
+                        self.overridden = false;
+                    }
+                });
+            }
+
+            ui.label("Day");
+            ui::strip_controls(ui, &mut self.day);
+            ui.label("Night");
+            ui::strip_controls(ui, &mut self.night);
+        });
+    }
+
+    fn strips_touched(&mut self, _strips: &[Strip]) {
+        self.overridden = true;
+    }
+
+    fn save(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timespec::tests::at;
+
+    const BERLIN: (f64, f64) = (52.52, 13.405);
+
+    #[test]
+    fn elevation_matches_the_almanac() {
+        // Midsummer noon in Berlin, 90 - 52.52 + 23.44.
+        let noon = elevation(at("2023-06-21T11:08:00Z"), BERLIN.0, BERLIN.1);
+        assert!((noon - 60.9).abs() < 0.3, "{noon}");
+        // Midwinter noon, 90 - 52.52 - 23.44.
+        let noon = elevation(at("2023-12-21T11:05:00Z"), BERLIN.0, BERLIN.1);
+        assert!((noon - 14.0).abs() < 0.3, "{noon}");
+        // Equinox, straight overhead on the equator.
+        let noon = elevation(at("2023-03-20T12:07:00Z"), 0.0, 0.0);
+        assert!(noon > 89.0, "{noon}");
+        let midnight = elevation(at("2023-06-21T23:08:00Z"), BERLIN.0, BERLIN.1);
+        assert!(midnight < -13.0, "{midnight}");
+    }
+
+    #[test]
+    fn finds_sunrise_and_sunset() {
+        let near = |got: SystemTime, want: &str| {
+            let want = at(want);
+            let off = got
+                .duration_since(want)
+                .or(want.duration_since(got))
+                .unwrap();
+            assert!(
+                off < Duration::from_secs(3 * 60),
+                "{}",
+                timespec::display(got)
+            );
+        };
+
+        let from = at("2023-06-21T00:00:00Z");
+        let (sunrise, rising) = next_crossing(from, BERLIN.0, BERLIN.1, HORIZON).unwrap();
+        assert!(rising);
+        near(sunrise, "2023-06-21T02:43:00Z");
+        let (sunset, rising) = next_crossing(sunrise, BERLIN.0, BERLIN.1, HORIZON).unwrap();
+        assert!(!rising);
+        near(sunset, "2023-06-21T19:33:00Z");
+
+        // Midnight sun.
+        assert!(next_crossing(from, 78.2, 15.6, HORIZON).is_none());
+    }
+
+    #[test]
+    fn daylight_eases_between_the_elevations() {
+        assert_eq!(daylight(-10.0, CIVIL_TWILIGHT, 3.0), 0.0);
+        assert_eq!(daylight(10.0, CIVIL_TWILIGHT, 3.0), 1.0);
+        assert_eq!(daylight(-1.5, CIVIL_TWILIGHT, 3.0), 0.5);
+        // Both at once is a hard switch.
+        assert_eq!(daylight(0.0, 0.0, 0.0), 1.0);
+        assert_eq!(daylight(-0.1, 0.0, 0.0), 0.0);
+
+        let night = [Strip(0, 1000)];
+        let day = [Strip(1000, 0)];
+        assert_eq!(blend(&night, &day, 0.0), night);
+        assert_eq!(blend(&night, &day, 0.5), [Strip(500, 500)]);
+        assert_eq!(blend(&night, &day, 1.0), day);
+    }
+}
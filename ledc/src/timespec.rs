@@ -0,0 +1,171 @@
+use std::time::SystemTime;
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Days, Local, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+
+/// Resolves a schedule start into the point in time it refers to, relative to `base`.
+///
+/// Accepts:
+/// - humantime durations, counted from `base` (`6h30m`)
+/// - a local time of day, meaning its next occurrence (`06:30`, `06:30:15`)
+/// - a day and a time (`today 22:00`, `tomorrow 07:00`, `2023-07-14 07:00`)
+/// - RFC 3339 timestamps (`2023-07-14T07:00:00+02:00`)
+pub fn resolve(spec: &str, base: SystemTime) -> Result<SystemTime> {
+    let spec = spec.trim();
+    if let Ok(dur) = humantime::parse_duration(spec) {
+        return Ok(base + dur);
+    }
+    if let Ok(at) = DateTime::parse_from_rfc3339(spec) {
+        return not_before(at.into(), base);
+    }
+
+    let base_local = DateTime::<Local>::from(base);
+    let (day, time) = match spec.split_once(char::is_whitespace) {
+        Some((day, time)) => (Some(day.trim()), time.trim()),
+        None => (None, spec),
+    };
+    let time = parse_time(time)?;
+
+    let date = match day {
+        None => {
+            // Bare times of day mean the next time the clock reads that.
+            let today = base_local.date_naive();
+            if local(today.and_time(time))? > base_local {
+                today
+            } else {
+                next_day(today)?
+            }
+        }
+        Some("today") => base_local.date_naive(),
+        Some("tomorrow") => next_day(base_local.date_naive())?,
+        Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|_| anyhow!("unknown day {date:?}"))?,
+    };
+
+    not_before(local(date.and_time(time))?.into(), base)
+}
+
+/// Formats a resolved start for display, in local time.
+pub fn display(at: SystemTime) -> String {
+    DateTime::<Local>::from(at)
+        .format("%a %Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+pub fn parse_time(time: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+        .map_err(|_| anyhow!("not a time of day: {time:?}"))
+}
+
+fn next_day(date: NaiveDate) -> Result<NaiveDate> {
+    date.checked_add_days(Days::new(1))
+        .ok_or_else(|| anyhow!("date out of range"))
+}
+
+pub fn local(at: NaiveDateTime) -> Result<DateTime<Local>> {
+    // DST gaps have no valid local time, overlaps have two; take the first. Not with
+    // `earliest()`, chrono doesn't always list them in order.
+    match Local.from_local_datetime(&at) {
+        LocalResult::Single(at) => Ok(at),
+        LocalResult::Ambiguous(a, b) => Ok(a.min(b)),
+        LocalResult::None => bail!("{at} doesn't exist in the local timezone"),
+    }
+}
+
+fn not_before(at: SystemTime, base: SystemTime) -> Result<SystemTime> {
+    if at < base {
+        bail!("{} is in the past", display(at));
+    }
+    Ok(at)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{sync::Once, time::Duration};
+
+    use super::*;
+
+    /// Pins the local timezone to one with DST, so tests don't depend on the machine's.
+    pub fn in_berlin() {
+        static TZ: Once = Once::new();
+        TZ.call_once(|| std::env::set_var("TZ", "Europe/Berlin"));
+    }
+
+    pub fn at(rfc3339: &str) -> SystemTime {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().into()
+    }
+
+    #[test]
+    fn durations_count_from_base() {
+        let base = at("2023-07-14T12:00:00Z");
+        assert_eq!(
+            resolve(" 6h30m ", base).unwrap(),
+            base + Duration::from_secs(6 * 3600 + 30 * 60)
+        );
+    }
+
+    #[test]
+    fn bare_times_mean_the_next_one() {
+        in_berlin();
+        let base = at("2023-07-14T12:00:00+02:00");
+        assert_eq!(
+            resolve("18:30", base).unwrap(),
+            at("2023-07-14T18:30:00+02:00")
+        );
+        assert_eq!(
+            resolve("06:30:15", base).unwrap(),
+            at("2023-07-15T06:30:15+02:00")
+        );
+        // Not this very second, that's already gone.
+        assert_eq!(
+            resolve("12:00", base).unwrap(),
+            at("2023-07-15T12:00:00+02:00")
+        );
+    }
+
+    #[test]
+    fn days_and_times() {
+        in_berlin();
+        let base = at("2023-07-14T12:00:00+02:00");
+        assert_eq!(
+            resolve("today 22:00", base).unwrap(),
+            at("2023-07-14T22:00:00+02:00")
+        );
+        assert_eq!(
+            resolve("tomorrow 07:00", base).unwrap(),
+            at("2023-07-15T07:00:00+02:00")
+        );
+        assert_eq!(
+            resolve("2023-12-24 18:00", base).unwrap(),
+            at("2023-12-24T18:00:00+01:00")
+        );
+        assert!(resolve("today 07:00", base).is_err(), "in the past");
+        assert!(resolve("someday 07:00", base).is_err());
+        assert!(resolve("today 25:00", base).is_err());
+    }
+
+    #[test]
+    fn rfc3339() {
+        let base = at("2023-07-14T12:00:00Z");
+        assert_eq!(
+            resolve("2023-07-15T07:00:00+02:00", base).unwrap(),
+            at("2023-07-15T05:00:00Z")
+        );
+        assert!(resolve("2023-07-13T07:00:00Z", base).is_err());
+    }
+
+    #[test]
+    fn dst_gaps_and_overlaps() {
+        in_berlin();
+        // 02:30 doesn't happen the night the clocks go forward.
+        let base = at("2023-03-25T12:00:00+01:00");
+        assert!(resolve("tomorrow 02:30", base).is_err());
+        // And happens twice when they go back, the first one counts.
+        let base = at("2023-10-28T12:00:00+02:00");
+        assert_eq!(
+            resolve("tomorrow 02:30", base).unwrap(),
+            at("2023-10-29T02:30:00+02:00")
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use std::{str::FromStr, time::SystemTime};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local, TimeZone};
+use chrono_tz::Tz;
+
+use crate::{controller::Manual, wave::Wave, Action, RelayAction, SharedAppData, Trigger};
+
+impl Trigger {
+    /// The next `n` times this fires after `after`.
+    ///
+    /// `cron` is `sec min hour day-of-month month day-of-week [year]`. Day of month and
+    /// day of week both have to match, so `0 0 9 1-7 * Mon` is the first Monday of the month.
+    pub fn upcoming(&self, after: SystemTime, n: usize) -> Result<Vec<SystemTime>> {
+        let schedule = cron::Schedule::from_str(&self.cron.0)?;
+        let timezone = self.timezone.0.trim();
+
+        fn take<Z: TimeZone>(
+            schedule: &cron::Schedule,
+            after: DateTime<Z>,
+            n: usize,
+        ) -> Vec<SystemTime> {
+            schedule
+                .after(&after)
+                .take(n)
+                .map(SystemTime::from)
+                .collect()
+        }
+
+        Ok(if timezone.is_empty() {
+            take(&schedule, DateTime::<Local>::from(after), n)
+        } else {
+            let tz =
+                Tz::from_str(timezone).map_err(|_| anyhow!("unknown timezone {timezone:?}"))?;
+            take(
+                &schedule,
+                DateTime::<Local>::from(after).with_timezone(&tz),
+                n,
+            )
+        })
+    }
+
+    /// Parses the text fields, flagging the ones that don't parse, and returns when this
+    /// fires next.
+    fn validate(&mut self, after: SystemTime) -> Option<SystemTime> {
+        self.cron.1 = cron::Schedule::from_str(&self.cron.0).err().map(|_| ());
+        self.timezone.1 = (!self.timezone.0.trim().is_empty()
+            && Tz::from_str(self.timezone.0.trim()).is_err())
+        .then_some(());
+        self.upcoming(after, 1).ok()?.first().copied()
+    }
+}
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::SetStrips(_) => "set strips",
+            Action::Wave(_) => "start wave",
+            Action::StartSchedule(_) => "start schedule",
+            Action::Relay(_) => "relay",
+            Action::RecallScene { .. } => "recall scene",
+        }
+    }
+
+    /// Does it, as of `now`.
+    pub fn run(&self, dat: &mut SharedAppData, now: SystemTime) {
+        match self {
+            Action::SetStrips(strips) => {
+                // A fixed state takes over from whatever was animating.
+                dat.controller = Box::new(Manual);
+                dat.strips = strips.clone();
+                dat.strips_changed = true;
+            }
+            Action::Wave(wave) => {
+                dat.controller = Box::new(Wave {
+                    started_at: now,
+                    // Swings around wherever the strips are when it fires.
+                    base: dat.strips.clone(),
+                    ..wave.clone()
+                });
+            }
+            Action::StartSchedule(name) => {
+                if let Some(schedule) = dat.schedules.iter_mut().find(|s| &s.name == name) {
+                    schedule.enabled = true;
+                    schedule.send = Some(now);
+                    schedule.status_changed = true;
+                } else {
+                    eprintln!("trigger wants schedule {name:?}, but there's no such thing");
+                }
+            }
+            Action::Relay(relay) => {
+                dat.relay_enabled = match relay {
+                    RelayAction::On => true,
+                    RelayAction::Off => false,
+                    RelayAction::Toggle => !dat.relay_enabled,
+                };
+                dat.relay_changed = true;
+            }
+            Action::RecallScene { name, fade } => {
+                let fade = humantime::parse_duration(&fade.0).unwrap_or_default();
+                if let Err(err) = dat.recall(name, fade) {
+                    eprintln!("trigger wants to recall a scene: {err:#}");
+                }
+            }
+        }
+    }
+}
+
+/// Fires every trigger that came due since we last looked.
+pub fn run_due(dat: &mut SharedAppData, now: SystemTime) {
+    let mut due = vec![];
+    for trigger in &mut dat.triggers {
+        if !trigger.enabled {
+            trigger.next = None;
+            continue;
+        }
+
+        match trigger.next {
+            Some(next) if next <= now => {
+                due.push(trigger.action.clone());
+                trigger.next = trigger.validate(now);
+            }
+            Some(_) => {}
+            None => trigger.next = trigger.validate(now),
+        }
+    }
+
+    for action in due {
+        action.run(dat, now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timespec::tests::{at, in_berlin};
+
+    fn trigger(cron: &str, timezone: &str) -> Trigger {
+        Trigger {
+            enabled: true,
+            cron: (cron.to_string(), None),
+            timezone: (timezone.to_string(), None),
+            ..Trigger::default()
+        }
+    }
+
+    #[test]
+    fn fires_in_its_timezone() {
+        in_berlin();
+        let after = at("2023-07-14T12:00:00Z");
+        assert_eq!(
+            trigger("0 0 9 * * *", "America/New_York")
+                .upcoming(after, 2)
+                .unwrap(),
+            [at("2023-07-14T13:00:00Z"), at("2023-07-15T13:00:00Z")]
+        );
+        // No timezone is local time.
+        assert_eq!(
+            trigger("0 0 9 * * *", "").upcoming(after, 1).unwrap(),
+            [at("2023-07-15T07:00:00Z")]
+        );
+    }
+
+    #[test]
+    fn follows_dst_in_its_timezone() {
+        in_berlin();
+        // New York falls back a week after Berlin does.
+        let after = at("2023-10-28T12:00:00Z");
+        assert_eq!(
+            trigger("0 0 9 * * *", "America/New_York")
+                .upcoming(after, 9)
+                .unwrap()[7..],
+            [at("2023-11-04T13:00:00Z"), at("2023-11-05T14:00:00Z")]
+        );
+        assert_eq!(
+            trigger("0 0 9 * * *", "")
+                .upcoming(at("2023-10-28T06:00:00Z"), 2)
+                .unwrap(),
+            [at("2023-10-28T07:00:00Z"), at("2023-10-29T08:00:00Z")]
+        );
+    }
+
+    #[test]
+    fn day_of_month_and_weekday_both_match() {
+        in_berlin();
+        let first_mondays = trigger("0 0 9 1-7 * Mon", "UTC");
+        assert_eq!(
+            first_mondays
+                .upcoming(at("2023-07-01T00:00:00Z"), 2)
+                .unwrap(),
+            [at("2023-07-03T09:00:00Z"), at("2023-08-07T09:00:00Z")]
+        );
+    }
+
+    #[test]
+    fn bad_fields_are_flagged() {
+        let now = SystemTime::now();
+        let mut bad_cron = trigger("every morning", "");
+        assert_eq!(bad_cron.validate(now), None);
+        assert_eq!(bad_cron.cron.1, Some(()));
+
+        let mut bad_timezone = trigger("0 0 9 * * *", "Europe/Atlantis");
+        assert!(bad_timezone.upcoming(now, 1).is_err());
+        assert_eq!(bad_timezone.validate(now), None);
+        assert_eq!(bad_timezone.cron.1, None);
+        assert_eq!(bad_timezone.timezone.1, Some(()));
+    }
+
+    #[test]
+    fn runs_what_came_due() {
+        let mut dat = SharedAppData::new();
+        dat.relay_enabled = false;
+        dat.triggers = vec![
+            Trigger {
+                action: Action::Relay(RelayAction::Toggle),
+                ..trigger("0 0 9 * * *", "UTC")
+            },
+            Trigger {
+                enabled: false,
+                action: Action::Relay(RelayAction::Toggle),
+                ..trigger("0 0 9 * * *", "UTC")
+            },
+        ];
+
+        run_due(&mut dat, at("2023-07-14T08:00:00Z"));
+        assert_eq!(dat.triggers[0].next, Some(at("2023-07-14T09:00:00Z")));
+        assert_eq!(dat.triggers[1].next, None);
+
+        run_due(&mut dat, at("2023-07-14T08:59:59Z"));
+        assert!(!dat.relay_enabled);
+        run_due(&mut dat, at("2023-07-14T09:00:01Z"));
+        assert!(dat.relay_enabled, "fires once");
+        assert_eq!(dat.triggers[0].next, Some(at("2023-07-15T09:00:00Z")));
+        run_due(&mut dat, at("2023-07-14T09:00:02Z"));
+        assert!(dat.relay_enabled);
+    }
+}
@@ -1,15 +1,30 @@
 use eframe::{
-    egui::{self, Grid, InnerResponse, TextEdit, Ui},
+    egui::{self, CollapsingHeader, ComboBox, Grid, InnerResponse, ProgressBar, TextEdit, Ui},
     epaint::Color32,
 };
 use std::{
+    path::Path,
     sync::atomic,
-    time::{Duration, Instant, SystemTime},
+    time::{Duration, SystemTime},
 };
 
-use eframe::egui::Slider;
+use chrono::{DateTime, Local};
+use eframe::egui::{
+    plot::{Legend, Line, Plot},
+    DragValue, Slider,
+};
 
-use crate::{Controller, LedApp, Strip, WaveType};
+use crate::{
+    animation::{self, Player},
+    controller,
+    easing::Easing,
+    schedule,
+    script::Script,
+    simulate, timespec,
+    wave::Wave,
+    Action, Animation, Calendar, CalendarEdge, CalendarRule, Idle, IdleAction, IdleSource, LedApp,
+    Preview, RelayAction, ScheduleUi, SharedAppData, Strip, Trigger,
+};
 
 impl eframe::App for LedApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
@@ -28,173 +43,395 @@ impl eframe::App for LedApp {
         ctx.request_repaint_after(Duration::from_secs_f32(1.0 / repaint_rate));
         let mut dat = self.shared.lock().unwrap(); // TODO very slow at startup if we happen to be in sync with the data update thread
         egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading("ledc");
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.heading("ledc");
 
-            ui.horizontal_wrapped(|ui| {
-                ui.group(|ui| {
-                    ui.label("Control mode");
-                    ui.radio_value(&mut dat.controller, Controller::Manual, "Manual");
-                    if ui
-                        .radio(matches!(dat.controller, Controller::Wave { .. }), "Wave")
-                        .clicked()
-                    {
-                        dat.controller = Controller::Wave {
-                            started_at: Instant::now(),
-                            interval_ms: 1000.0,
-                            warm: true,
-                            cold: false,
-                            ty: WaveType::Sine,
-                        };
+                ui.horizontal_wrapped(|ui| {
+                    ui.group(|ui| {
+                        let label = ui.label("Control mode");
+                        if let Ok(dir) = Script::dir() {
+                            label.on_hover_text(format!("Scripts go in {}", dir.display()));
+                        }
+                        for registration in controller::REGISTRY {
+                            // Every script and animation gets one of its own.
+                            let labels = match registration.name {
+                                "Script" => self.scripts.get().to_vec(),
+                                "Animation" => dat
+                                    .animations
+                                    .iter()
+                                    .map(|animation| animation.name.clone())
+                                    .collect(),
+                                name => vec![name.to_string()],
+                            };
+                            for (i, label) in labels.into_iter().enumerate() {
+                                let selected = dat.controller.name() == registration.name
+                                    && dat.controller.label() == label;
+                                let radio = ui.radio(selected, &label);
+                                let radio = if selected {
+                                    radio.on_hover_text(format!("{:?}", dat.controller))
+                                } else {
+                                    radio
+                                };
+                                if radio.clicked() && !selected {
+                                    let dat = &mut *dat;
+                                    match registration.name {
+                                        "Script" => dat.controller = Box::new(Script::new(label)),
+                                        "Animation" => dat.play(i, SystemTime::now()),
+                                        _ => dat.controller = (registration.new)(),
+                                    }
+                                    dat.controller.strips_touched(&dat.strips);
+                                }
+                            }
+                        }
+                    });
+                    if ui.checkbox(&mut dat.relay_enabled, "Relay").changed() {
+                        dat.relay_changed = true;
                     }
                 });
-                if ui.checkbox(&mut dat.relay_enabled, "Relay").changed() {
-                    dat.relay_changed = true;
+
+                let capped = ui
+                    .horizontal(|ui| {
+                        ui.label("Caps");
+                        let cold = ui.add(Slider::new(&mut dat.caps.0, 0..=u16::MAX).text("cold"));
+                        let warm = ui.add(Slider::new(&mut dat.caps.1, 0..=u16::MAX).text("warm"));
+                        cold.changed() || warm.changed()
+                    })
+                    .inner;
+                if capped {
+                    // Whatever's loaded on the MCU was capped to the old ones.
+                    dat.resync();
                 }
-            });
 
-            let make_strip_controls =
-                |ui: &mut Ui, strips: &mut Vec<Strip>| -> InnerResponse<bool> {
-                    ui.horizontal_wrapped(|ui| {
-                        let mut changed = false;
-                        for (i, strip) in strips.iter_mut().enumerate() {
-                            changed |= ui
-                                .vertical(|ui| {
-                                    ui.group(|ui| {
-                                        ui.label(format!("Strip {i}"));
-                                        let mut changed = false;
-                                        changed |= ui
-                                            .add(Slider::new(&mut strip.0, 0..=65535).text("cold"))
-                                            .changed();
-                                        changed |= ui
-                                            .add(Slider::new(&mut strip.1, 0..=65535).text("warm"))
-                                            .changed();
-                                        changed
-                                    })
-                                    .inner
-                                })
-                                .inner;
+                let touched = strip_controls(ui, &mut dat.strips).inner;
+                dat.strips_changed |= touched;
+                if touched {
+                    let dat = &mut *dat;
+                    dat.fade = None;
+                    dat.controller.strips_touched(&dat.strips);
+                }
+
+                self.poll_update_fast = dat.controller.realtime() || dat.fade.is_some();
+                dat.controller.ui(ui);
+
+                ui.group(|ui| {
+                    ui.label("Scenes");
+
+                    let dat = &mut *dat;
+                    let mut recall = None;
+                    let mut remove = None;
+                    for (i, scene) in dat.scenes.iter_mut().enumerate() {
+                        ui.push_id(("scene", i), |ui| {
+                            ui.horizontal(|ui| {
+                                let default = dat.default_scene.as_ref() == Some(&scene.name);
+                                let name = ui
+                                    .add(TextEdit::singleline(&mut scene.name).desired_width(120.));
+                                if name.changed() && default {
+                                    dat.default_scene = Some(scene.name.clone());
+                                }
+                                if ui.button("Recall").clicked() {
+                                    recall = Some(scene.name.clone());
+                                }
+                                if ui.button("Update").clicked() {
+                                    scene.strips = dat.strips.clone();
+                                    scene.relay = dat.relay_enabled;
+                                }
+                                if ui
+                                    .selectable_label(default, "default")
+                                    .on_hover_text("Recalled whenever ledc starts")
+                                    .clicked()
+                                {
+                                    dat.default_scene = (!default).then(|| scene.name.clone());
+                                }
+                                if ui.small_button("remove").clicked() {
+                                    remove = Some(i);
+                                }
+                            });
+                        });
+                    }
+
+                    if let Some(i) = remove {
+                        let scene = dat.scenes.remove(i);
+                        if dat.default_scene == Some(scene.name) {
+                            dat.default_scene = None;
                         }
-                        changed
-                    })
-                };
-
-            dat.strips_changed |= make_strip_controls(ui, &mut dat.strips).inner;
-
-            self.poll_update_fast = false;
-            if let Controller::Wave {
-                started_at,
-                interval_ms,
-                warm,
-                cold,
-                ty,
-            } = &mut dat.controller
-            {
-                self.poll_update_fast = true;
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("fade over");
+                        if duration_field(ui, &mut self.scene_fade) {
+                            self.scene_fade.1 = humantime::parse_duration(&self.scene_fade.0)
+                                .err()
+                                .map(|_| ());
+                        }
+                        ui.add(
+                            TextEdit::singleline(&mut self.scene_name)
+                                .desired_width(120.)
+                                .hint_text("evening reading"),
+                        );
+                        if ui.button("Save as scene").clicked()
+                            && dat.save_scene(self.scene_name.trim()).is_ok()
+                        {
+                            self.scene_name.clear();
+                        }
+                    });
+                    if let Some(name) = recall {
+                        let fade =
+                            humantime::parse_duration(&self.scene_fade.0).unwrap_or_default();
+                        dat.recall(&name, fade).ok();
+                    }
+                });
+
                 ui.group(|ui| {
-                    ui.label("Slide controls");
-                    let mut immut_pos = (started_at.elapsed().as_millis() as f32) % *interval_ms;
-                    ui.add_enabled(
-                        false,
-                        Slider::new(&mut immut_pos, 0.0..=*interval_ms).text("current"),
-                    );
-                    ui.group(|ui| {
-                        ui.label("Wave type");
-                        ui.vertical(|ui| {
-                            ui.radio_value(ty, WaveType::Sine, "Sine");
-
-                            if ui
-                                .radio(matches!(*ty, WaveType::Square(..)), "Square")
-                                .clicked()
-                            {
-                                *ty = WaveType::Square(0.1);
-                            }
+                    ui.label("Schedules");
 
-                            if let WaveType::Square(duty) = ty {
-                                ui.add(Slider::new(duty, 0.0..=1.0).text("duty"));
+                    let timelines = schedule::timelines(&mut dat.schedules);
+                    let windows = schedule::windows(&dat.schedules, &timelines);
+                    match schedule::pick(&windows, SystemTime::now()) {
+                        Some(pick) => ui.label(format!(
+                            "{} has the lights: {}",
+                            dat.schedules[pick.index].name, pick.why
+                        )),
+                        None => ui.label("Nothing armed"),
+                    };
+
+                    ui.horizontal(|ui| {
+                        ui.label("Preview at");
+                        ui.add(
+                            DragValue::new(&mut self.preview_speed)
+                                .clamp_range(1.0..=3600.0)
+                                .suffix("x"),
+                        );
+                        if let Some(preview) = &dat.preview {
+                            ui.add(
+                                ProgressBar::new(preview.progress())
+                                    .desired_width(120.)
+                                    .text(preview.name.as_str()),
+                            );
+                            if ui.small_button("stop").clicked() {
+                                dat.stop_preview();
                             }
-                        })
-                    });
-                    if ui
-                        .add(
-                            Slider::new(interval_ms, 5.0..=100_000.0)
-                                .text("interval")
-                                .logarithmic(true),
-                        )
-                        .changed()
-                    {
-                        *started_at = Instant::now();
-                    }
-                    ui.label("Affecting");
-                    ui.vertical(|ui| {
-                        ui.checkbox(warm, "warm");
-                        ui.checkbox(cold, "cold");
+                        }
                     });
+
+                    let dat = &mut *dat;
+                    let mut remove = None;
+                    for (i, schedule) in dat.schedules.iter_mut().enumerate() {
+                        ui.push_id(i, |ui| {
+                            let window = windows.iter().find(|w| w.index == i);
+                            let status = match (schedule.enabled, window) {
+                                (false, _) => "disabled".to_string(),
+                                (true, None) => "idle".to_string(),
+                                (true, Some(w)) if w.begin > SystemTime::now() => {
+                                    format!("armed for {}", timespec::display(w.begin))
+                                }
+                                (true, Some(w)) => {
+                                    format!("running until {}", timespec::display(w.end))
+                                }
+                            };
+                            CollapsingHeader::new(format!("{} ({status})", schedule.name))
+                                .id_source("schedule")
+                                .default_open(true)
+                                .show(ui, |ui| {
+                                    if schedule_controls(
+                                        ui,
+                                        schedule,
+                                        &mut dat.strips,
+                                        &mut dat.strips_changed,
+                                        &mut dat.preview,
+                                        self.preview_speed,
+                                    ) {
+                                        remove = Some(i);
+                                    }
+                                });
+                        });
+                    }
+
+                    if let Some(i) = remove {
+                        dat.schedules.remove(i);
+                    }
+                    if ui.button("Add schedule").clicked() {
+                        let name = format!("Schedule {}", dat.schedules.len());
+                        dat.schedules.push(ScheduleUi::new(name));
+                    }
                 });
-            }
 
-            ui.group(|ui| {
-                ui.horizontal(|ui| {
-                    let btn = ui.selectable_label(dat.schedule.send.is_some(), "Schedule");
-                    if btn.clicked() {
-                        if dat.schedule.send.is_none() {
-                            dat.schedule.send = Some(SystemTime::now());
-                        } else {
-                            // Force push of controller state.
-                            dat.schedule.send = None;
-                            dat.strips_changed = true;
-                        }
-                        dat.schedule.status_changed = true;
-                    } else if btn.secondary_clicked() {
-                        // Swap
-                        let prev = dat.strips.clone();
-                        dat.strips = dat.schedule.endpoint.clone();
-                        dat.schedule.endpoint = prev;
+                ui.group(|ui| {
+                    ui.label("Triggers");
+
+                    let dat = &mut *dat;
+                    let names = Names::of(dat);
+                    let mut remove = None;
+                    for (i, trigger) in dat.triggers.iter_mut().enumerate() {
+                        ui.push_id(("trigger", i), |ui| {
+                            CollapsingHeader::new(&trigger.name)
+                                .id_source("trigger")
+                                .default_open(true)
+                                .show(ui, |ui| {
+                                    if trigger_controls(ui, trigger, &names) {
+                                        remove = Some(i);
+                                    }
+                                });
+                        });
                     }
 
-                    ui.checkbox(&mut dat.schedule.swap_on_stop, "Swap on stop");
+                    if let Some(i) = remove {
+                        dat.triggers.remove(i);
+                    }
+                    if ui.button("Add trigger").clicked() {
+                        let name = format!("Trigger {}", dat.triggers.len());
+                        dat.triggers.push(Trigger::new(name));
+                    }
                 });
 
-                if {
-                    ui.vertical(|ui| {
-                        ui.horizontal(|ui| {
-                            Grid::new("schedule_grid")
-                                .num_columns(2)
+                ui.group(|ui| {
+                    ui.label("Calendars");
+
+                    let dat = &mut *dat;
+                    let names = Names::of(dat);
+                    let mut remove = None;
+                    for (i, calendar) in dat.calendars.iter_mut().enumerate() {
+                        ui.push_id(("calendar", i), |ui| {
+                            let title = if calendar.path.is_empty() {
+                                "New calendar"
+                            } else {
+                                calendar.path.as_str()
+                            };
+                            CollapsingHeader::new(title)
+                                .id_source("calendar")
+                                .default_open(true)
                                 .show(ui, |ui| {
-                                    let mut changed = false;
-                                    ui.label("begin after");
-
-                                    let start_color = dat.schedule.start.1.map(|_| Color32::RED);
-                                    changed |= ui
-                                        .add(
-                                            TextEdit::singleline(&mut dat.schedule.start.0)
-                                                .desired_width(80.)
-                                                .text_color_opt(start_color),
-                                        )
-                                        .changed();
-                                    ui.end_row();
-
-                                    let length_color = dat.schedule.length.1.map(|_| Color32::RED);
-                                    ui.label("transition length");
-                                    changed |= ui
-                                        .add(
-                                            TextEdit::singleline(&mut dat.schedule.length.0)
-                                                .desired_width(80.)
-                                                .text_color_opt(length_color),
-                                        )
-                                        .changed();
-                                    ui.end_row();
-
-                                    changed
-                                })
-                                .inner
-                        })
-                        .inner
-                    })
-                    .inner
-                } || make_strip_controls(ui, &mut dat.schedule.endpoint).inner
-                {
-                    dat.schedule.send = None;
-                }
+                                    if calendar_controls(ui, calendar, &names) {
+                                        remove = Some(i);
+                                    }
+                                });
+                        });
+                    }
+
+                    if let Some(i) = remove {
+                        dat.calendars.remove(i);
+                    }
+                    if ui.button("Add calendar").clicked() {
+                        dat.calendars.push(Calendar::new());
+                    }
+                });
+
+                ui.group(|ui| {
+                    let label = ui.label("Animations");
+                    if let Ok(dir) = Animation::dir() {
+                        label.on_hover_text(format!("Exported to {}", dir.display()));
+                    }
+
+                    let dat = &mut *dat;
+                    let now = SystemTime::now();
+                    let mut remove = None;
+                    for i in 0..dat.animations.len() {
+                        let playing = dat.playing(i);
+                        let animation = &mut dat.animations[i];
+                        let mut play = false;
+                        let mut changed = false;
+                        ui.push_id(("animation", i), |ui| {
+                            let title = if playing {
+                                format!("{} (playing)", animation.name)
+                            } else {
+                                animation.name.clone()
+                            };
+                            CollapsingHeader::new(title)
+                                .id_source("animation")
+                                .default_open(true)
+                                .show(ui, |ui| {
+                                    ui.horizontal(|ui| {
+                                        changed |= ui
+                                            .add(
+                                                TextEdit::singleline(&mut animation.name)
+                                                    .desired_width(120.),
+                                            )
+                                            .changed();
+                                        play = ui.button("Play").clicked();
+                                        if ui.button("Export").clicked() {
+                                            self.animation_status = match animation.export() {
+                                                Ok(path) => {
+                                                    format!("Exported to {}", path.display())
+                                                }
+                                                Err(err) => format!("Couldn't export: {err:#}"),
+                                            };
+                                        }
+                                        if ui.small_button("remove").clicked() {
+                                            remove = Some(i);
+                                        }
+                                    });
+                                    let playhead = animation.playhead(now).filter(|_| playing);
+                                    changed |= animation.editor(ui, playhead);
+                                });
+                        });
+                        if play {
+                            dat.play(i, now);
+                        } else if changed && playing {
+                            // Keeps playing from where it was, edits and all.
+                            dat.controller = Box::new(Player::new(dat.animations[i].clone()));
+                        }
+                    }
+
+                    if let Some(i) = remove {
+                        dat.animations.remove(i);
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Add animation").clicked() {
+                            let name = animation::unique_name(&dat.animations, "Animation");
+                            dat.animations.push(Animation::new(name, &dat.strips));
+                        }
+                        ui.add(
+                            TextEdit::singleline(&mut self.animation_file)
+                                .desired_width(200.)
+                                .hint_text("/path/to/animation.json"),
+                        );
+                        if ui.button("Import").clicked() {
+                            self.animation_status =
+                                match Animation::import(Path::new(&self.animation_file)) {
+                                    Ok(mut animation) => {
+                                        animation.name = animation::unique_name(
+                                            &dat.animations,
+                                            &animation.name,
+                                        );
+                                        let status = format!("Imported {}", animation.name);
+                                        dat.animations.push(animation);
+                                        status
+                                    }
+                                    Err(err) => format!("Couldn't import: {err:#}"),
+                                };
+                        }
+                    });
+                    if !self.animation_status.is_empty() {
+                        ui.label(&self.animation_status);
+                    }
+                });
+
+                ui.group(|ui| {
+                    ui.label("Idle dimming");
+                    idle_controls(ui, &mut dat.idle);
+                });
+
+                ui.group(|ui| {
+                    ui.label("Simulation");
+
+                    ui.horizontal(|ui| {
+                        ui.label("the next");
+                        duration_field(ui, &mut self.simulation_span);
+                        if ui.button("Simulate").clicked() {
+                            let span = humantime::parse_duration(&self.simulation_span.0);
+                            self.simulation_span.1 = span.as_ref().err().map(|_| ());
+                            if let Ok(span) = span {
+                                // About a point per pixel is plenty.
+                                let step = (span / 500).max(Duration::from_secs(1));
+                                let now = SystemTime::now();
+                                self.simulation =
+                                    simulate::simulate(&dat, now, now + span, step).ok();
+                            }
+                        }
+                    });
+
+                    if let Some(samples) = &self.simulation {
+                        simulation_plot(ui, samples);
+                    }
+                });
             });
         });
 
@@ -208,3 +445,488 @@ impl eframe::App for LedApp {
         }
     }
 }
+
+const WEEKDAYS: [&str; 7] = ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"];
+
+/// Idle dimming's settings, and what it makes of the idle time.
+fn idle_controls(ui: &mut Ui, idle: &mut Idle) {
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut idle.enabled, "Enabled");
+        ui.label("after");
+        duration_field(ui, &mut idle.timeout);
+        ui.label("without input, using");
+        for (source, name) in [
+            (IdleSource::Auto, "auto"),
+            (IdleSource::X11, "X11"),
+            (IdleSource::Logind, "logind"),
+        ] {
+            ui.radio_value(&mut idle.source, source, name);
+        }
+    });
+    ui.horizontal(|ui| {
+        let dimming = matches!(idle.action, IdleAction::Dim(_));
+        if ui.radio(dimming, "dim").clicked() && !dimming {
+            idle.action = IdleAction::Dim(0.2);
+        }
+        if ui.radio(!dimming, "turn off").clicked() && dimming {
+            idle.action = IdleAction::Off;
+        }
+        if let IdleAction::Dim(level) = &mut idle.action {
+            ui.add(Slider::new(level, 0.0..=1.0).text("of the brightness"));
+        }
+    });
+    if !idle.status.is_empty() {
+        let away = if idle.away { ", away" } else { "" };
+        ui.label(format!("{}{away}", idle.status));
+    }
+}
+
+/// A text field that turns red when it doesn't parse.
+fn duration_field(ui: &mut Ui, field: &mut (String, Option<()>)) -> bool {
+    let color = field.1.map(|_| Color32::RED);
+    ui.add(
+        TextEdit::singleline(&mut field.0)
+            .desired_width(80.)
+            .text_color_opt(color),
+    )
+    .changed()
+}
+
+pub fn strip_controls(ui: &mut Ui, strips: &mut [Strip]) -> InnerResponse<bool> {
+    ui.horizontal_wrapped(|ui| {
+        let mut changed = false;
+        for (i, strip) in strips.iter_mut().enumerate() {
+            changed |= ui
+                .vertical(|ui| {
+                    ui.group(|ui| {
+                        ui.label(format!("Strip {i}"));
+                        let mut changed = false;
+                        changed |= ui
+                            .add(Slider::new(&mut strip.0, 0..=65535).text("cold"))
+                            .changed();
+                        changed |= ui
+                            .add(Slider::new(&mut strip.1, 0..=65535).text("warm"))
+                            .changed();
+                        changed
+                    })
+                    .inner
+                })
+                .inner;
+        }
+        changed
+    })
+}
+
+/// Everything about one schedule. Returns whether the user wants it gone.
+fn schedule_controls(
+    ui: &mut Ui,
+    schedule: &mut ScheduleUi,
+    strips: &mut Vec<Strip>,
+    strips_changed: &mut bool,
+    preview: &mut Option<Preview>,
+    preview_speed: f32,
+) -> bool {
+    let mut remove_schedule = false;
+    ui.horizontal(|ui| {
+        if ui.checkbox(&mut schedule.enabled, "").changed() && !schedule.enabled {
+            // Force push of controller state.
+            schedule.send = None;
+            *strips_changed = true;
+        }
+        ui.add(TextEdit::singleline(&mut schedule.name).desired_width(120.));
+        ui.add(DragValue::new(&mut schedule.priority).prefix("priority "));
+        remove_schedule = ui.small_button("remove").clicked();
+    });
+
+    ui.horizontal(|ui| {
+        let btn = ui.selectable_label(schedule.send.is_some(), "Schedule");
+        if btn.clicked() {
+            if schedule.send.is_none() {
+                schedule.send = Some(SystemTime::now());
+            } else {
+                // A recurring one re-arms for the next occurrence after now, which is the
+                // one it was waiting on if that hasn't begun yet.
+                let waiting = schedule.begins_at().is_ok_and(|at| at > SystemTime::now());
+                if schedule.recurrence.enabled && waiting {
+                    schedule.recurrence.skip_next = true;
+                }
+                // Force push of controller state.
+                schedule.send = None;
+                *strips_changed = true;
+            }
+            schedule.status_changed = true;
+        } else if btn.secondary_clicked() {
+            // Swap
+            let prev = strips.clone();
+            if let Some(endpoint) = schedule.endpoint_mut() {
+                *strips = std::mem::replace(endpoint, prev);
+            }
+        }
+
+        ui.checkbox(&mut schedule.swap_on_stop, "Swap on stop");
+
+        // Doesn't touch `send`, the armed run goes on as it was once this is over.
+        if ui.button("Preview").clicked() {
+            *preview = Preview::new(schedule, strips.clone(), preview_speed);
+        }
+    });
+
+    let mut changed = Grid::new("schedule_grid")
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("begin at/after");
+            let mut changed = ui
+                .add_enabled_ui(!schedule.recurrence.enabled, |ui| {
+                    duration_field(ui, &mut schedule.start)
+                })
+                .inner;
+            ui.end_row();
+
+            let recurrence = &mut schedule.recurrence;
+            changed |= ui.checkbox(&mut recurrence.enabled, "repeat at").changed();
+            ui.horizontal(|ui| {
+                changed |= duration_field(ui, &mut recurrence.time);
+                for (enabled, day) in recurrence.weekdays.iter_mut().zip(WEEKDAYS) {
+                    if ui.selectable_label(*enabled, day).clicked() {
+                        *enabled = !*enabled;
+                        changed = true;
+                    }
+                }
+                changed |= ui
+                    .checkbox(&mut recurrence.skip_next, "skip next")
+                    .changed();
+            });
+            ui.end_row();
+
+            // Absolute times are resolved relative to when the schedule was sent.
+            ui.label("");
+            match schedule.begins_at() {
+                Ok(at) => ui.label(timespec::display(at)),
+                Err(err) => ui.colored_label(Color32::RED, err.to_string()),
+            };
+            ui.end_row();
+
+            if let Some(err) = &schedule.range_error {
+                ui.label("");
+                ui.colored_label(Color32::RED, err);
+                ui.end_row();
+            }
+
+            changed
+        })
+        .inner;
+
+    let removable = schedule.keyframes.len() > 1;
+    let mut remove = None;
+    for (i, keyframe) in schedule.keyframes.iter_mut().enumerate() {
+        ui.group(|ui| {
+            ui.horizontal(|ui| {
+                ui.label(format!("Keyframe {i}"));
+                if removable && ui.small_button("remove").clicked() {
+                    remove = Some(i);
+                }
+            });
+
+            changed |= Grid::new(("keyframe_grid", i))
+                .num_columns(2)
+                .show(ui, |ui| {
+                    let mut changed = false;
+                    ui.label("hold");
+                    changed |= duration_field(ui, &mut keyframe.offset);
+                    ui.end_row();
+
+                    ui.label("transition length");
+                    changed |= duration_field(ui, &mut keyframe.duration);
+                    ui.end_row();
+
+                    ui.label("easing");
+                    ComboBox::from_id_source(("keyframe_easing", i))
+                        .selected_text(keyframe.easing.name())
+                        .show_ui(ui, |ui| {
+                            for easing in Easing::ALL {
+                                changed |= ui
+                                    .selectable_value(&mut keyframe.easing, easing, easing.name())
+                                    .changed();
+                            }
+                        });
+                    ui.end_row();
+
+                    changed
+                })
+                .inner;
+
+            changed |= strip_controls(ui, &mut keyframe.target).inner;
+        });
+    }
+
+    if let Some(i) = remove {
+        schedule.keyframes.remove(i);
+        changed = true;
+    }
+    if ui.button("Add keyframe").clicked() {
+        if let Some(last) = schedule.keyframes.last().cloned() {
+            schedule.keyframes.push(last);
+        }
+        changed = true;
+    }
+
+    if changed {
+        schedule.send = None;
+    }
+
+    remove_schedule
+}
+
+/// Everything about one trigger. Returns whether the user wants it gone.
+fn trigger_controls(ui: &mut Ui, trigger: &mut Trigger, names: &Names) -> bool {
+    let mut remove_trigger = false;
+    let mut changed = false;
+    ui.horizontal(|ui| {
+        changed |= ui.checkbox(&mut trigger.enabled, "").changed();
+        ui.add(TextEdit::singleline(&mut trigger.name).desired_width(120.));
+        remove_trigger = ui.small_button("remove").clicked();
+    });
+
+    Grid::new("trigger_grid").num_columns(2).show(ui, |ui| {
+        ui.label("cron");
+        let color = trigger.cron.1.map(|_| Color32::RED);
+        changed |= ui
+            .add(
+                TextEdit::singleline(&mut trigger.cron.0)
+                    .desired_width(200.)
+                    .hint_text("sec min hour dom month dow")
+                    .text_color_opt(color),
+            )
+            .changed();
+        ui.end_row();
+
+        ui.label("timezone");
+        let color = trigger.timezone.1.map(|_| Color32::RED);
+        changed |= ui
+            .add(
+                TextEdit::singleline(&mut trigger.timezone.0)
+                    .desired_width(200.)
+                    .hint_text("local")
+                    .text_color_opt(color),
+            )
+            .changed();
+        ui.end_row();
+
+        ui.label("next");
+        ui.vertical(|ui| match trigger.upcoming(SystemTime::now(), 3) {
+            Ok(times) if times.is_empty() => {
+                ui.label("never");
+            }
+            Ok(times) => {
+                for at in times {
+                    ui.label(timespec::display(at));
+                }
+            }
+            Err(err) => {
+                ui.colored_label(Color32::RED, err.to_string());
+            }
+        });
+        ui.end_row();
+    });
+
+    action_controls(ui, &mut trigger.action, names);
+
+    if changed {
+        trigger.next = None;
+    }
+
+    remove_trigger
+}
+
+/// Everything about one calendar. Returns whether the user wants it gone.
+fn calendar_controls(ui: &mut Ui, calendar: &mut Calendar, names: &Names) -> bool {
+    let mut remove_calendar = false;
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut calendar.enabled, "");
+        ui.add(
+            TextEdit::singleline(&mut calendar.path)
+                .desired_width(200.)
+                .hint_text("/path/to/calendar.ics"),
+        );
+        remove_calendar = ui.small_button("remove").clicked();
+    });
+    if calendar.enabled {
+        ui.label(&calendar.status);
+    }
+
+    let mut remove = None;
+    for (i, rule) in calendar.rules.iter_mut().enumerate() {
+        ui.push_id(("rule", i), |ui| {
+            ui.group(|ui| {
+                ui.horizontal(|ui| {
+                    ui.label("events matching");
+                    ui.add(
+                        TextEdit::singleline(&mut rule.matching)
+                            .desired_width(120.)
+                            .hint_text("anything"),
+                    );
+                    if ui.small_button("remove").clicked() {
+                        remove = Some(i);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    duration_field(ui, &mut rule.lead);
+                    ui.label("before");
+                    ComboBox::from_id_source("edge")
+                        .selected_text(edge_name(rule.edge))
+                        .show_ui(ui, |ui| {
+                            for edge in [
+                                CalendarEdge::Start,
+                                CalendarEdge::End,
+                                CalendarEdge::LastEnd,
+                            ] {
+                                ui.selectable_value(&mut rule.edge, edge, edge_name(edge));
+                            }
+                        });
+                });
+                action_controls(ui, &mut rule.action, names);
+            });
+        });
+    }
+    if let Some(i) = remove {
+        calendar.rules.remove(i);
+    }
+    if ui.button("Add rule").clicked() {
+        calendar.rules.push(CalendarRule::new());
+    }
+
+    if calendar.enabled && !calendar.upcoming.is_empty() {
+        ui.label("next");
+        for (at, rule) in calendar.upcoming.iter().take(3) {
+            ui.label(format!("{}, rule {}", timespec::display(*at), rule + 1));
+        }
+    }
+
+    remove_calendar
+}
+
+fn edge_name(edge: CalendarEdge) -> &'static str {
+    match edge {
+        CalendarEdge::Start => "it starts",
+        CalendarEdge::End => "it ends",
+        CalendarEdge::LastEnd => "the day's last one ends",
+    }
+}
+
+/// What actions can refer to by name.
+struct Names {
+    schedules: Vec<String>,
+    scenes: Vec<String>,
+}
+
+impl Names {
+    fn of(dat: &SharedAppData) -> Self {
+        Names {
+            schedules: dat.schedules.iter().map(|s| s.name.clone()).collect(),
+            scenes: dat.scenes.iter().map(|s| s.name.clone()).collect(),
+        }
+    }
+}
+
+/// Picks an [`Action`] and edits its settings.
+fn action_controls(ui: &mut Ui, action: &mut Action, names: &Names) {
+    ui.horizontal(|ui| {
+        ui.label("action");
+        ComboBox::from_id_source("action")
+            .selected_text(action.name())
+            .show_ui(ui, |ui| {
+                for option in [
+                    Action::SetStrips(vec![Strip(0, 0); 2]),
+                    Action::Wave(Wave::default()),
+                    Action::StartSchedule(names.schedules.first().cloned().unwrap_or_default()),
+                    Action::Relay(RelayAction::Toggle),
+                    Action::RecallScene {
+                        name: names.scenes.first().cloned().unwrap_or_default(),
+                        fade: ("0s".to_string(), None),
+                    },
+                ] {
+                    let selected = action.name() == option.name();
+                    if ui.selectable_label(selected, option.name()).clicked() && !selected {
+                        *action = option;
+                    }
+                }
+            });
+    });
+
+    match action {
+        Action::SetStrips(strips) => {
+            strip_controls(ui, strips);
+        }
+        Action::Wave(wave) => {
+            wave.settings_ui(ui, None);
+        }
+        Action::StartSchedule(name) => {
+            ComboBox::from_id_source("action_schedule")
+                .selected_text(name.as_str())
+                .show_ui(ui, |ui| {
+                    for schedule in &names.schedules {
+                        ui.selectable_value(name, schedule.clone(), schedule);
+                    }
+                });
+        }
+        Action::Relay(relay) => {
+            ui.horizontal(|ui| {
+                ui.radio_value(relay, RelayAction::On, "on");
+                ui.radio_value(relay, RelayAction::Off, "off");
+                ui.radio_value(relay, RelayAction::Toggle, "toggle");
+            });
+        }
+        Action::RecallScene { name, fade } => {
+            ui.horizontal(|ui| {
+                ComboBox::from_id_source("action_scene")
+                    .selected_text(name.as_str())
+                    .show_ui(ui, |ui| {
+                        for scene in &names.scenes {
+                            ui.selectable_value(name, scene.clone(), scene);
+                        }
+                    });
+                ui.label("fading over");
+                if duration_field(ui, fade) {
+                    fade.1 = humantime::parse_duration(&fade.0).err().map(|_| ());
+                }
+            });
+        }
+    }
+}
+
+/// Every channel of a simulation over time, in hours from its start.
+fn simulation_plot(ui: &mut Ui, samples: &[simulate::Sample]) {
+    let Some(start) = samples.first().map(|sample| sample.at) else {
+        return;
+    };
+    let hours =
+        |at: SystemTime| at.duration_since(start).unwrap_or_default().as_secs_f64() / 3600.0;
+
+    Plot::new("simulation")
+        .height(200.)
+        .include_y(0.0)
+        .include_y(100.0)
+        .legend(Legend::default())
+        .x_axis_formatter(move |x, _| {
+            let at = start + Duration::from_secs_f64(x.max(0.0) * 3600.0);
+            DateTime::<Local>::from(at).format("%a %H:%M").to_string()
+        })
+        .label_formatter(|name, point| format!("{name}: {:.1}%", point.y))
+        .show(ui, |plot| {
+            let strips = samples[0].strips.len();
+            for (i, channel) in simulate::channels(strips).into_iter().enumerate() {
+                let points = samples
+                    .iter()
+                    .map(|sample| {
+                        let strip = &sample.strips[i / 2];
+                        let value = if i % 2 == 0 { strip.0 } else { strip.1 };
+                        [
+                            hours(sample.at),
+                            f64::from(value) / f64::from(u16::MAX) * 100.0,
+                        ]
+                    })
+                    .collect::<Vec<_>>();
+                plot.line(Line::new(points).name(channel));
+            }
+        });
+}
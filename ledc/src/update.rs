@@ -1,21 +1,92 @@
 use std::{
     io::Write,
-    sync::{Arc, Mutex},
+    sync::{mpsc::Receiver, Arc, Mutex},
     thread::{self, sleep},
-    time::{Duration, SystemTime},
+    time::{Duration, Instant},
 };
 
 use anyhow::Result;
 use serialport::SerialPort;
 
-use crate::{open_serial, Controller, SharedAppData, Strip, WaveType};
+use crate::{
+    calendar,
+    clock::{Clock, JumpDetector, Sleep},
+    idle, open_serial, schedule, trigger, SharedAppData, Strip,
+};
+
+/// How often a loaded segment gets resynced with the MCU.
+const RESYNC: Duration = Duration::from_secs(10 * 60);
+
+impl SharedAppData {
+    /// Has the MCU reload whatever's armed, just like after a restart, for after something
+    /// had the lights to itself.
+    pub fn resync(&mut self) {
+        for schedule in &mut self.schedules {
+            schedule.status_changed = true;
+        }
+        self.strips_changed = true;
+    }
+
+    /// `strips` held down to the caps.
+    pub fn capped(&self, strips: &[Strip]) -> Vec<Strip> {
+        strips
+            .iter()
+            .map(|strip| Strip(strip.0.min(self.caps.0), strip.1.min(self.caps.1)))
+            .collect()
+    }
+}
+
+/// Whether we came back from suspend or the clock changed since the last round, either of
+/// which invalidates most of what we know.
+fn resumed(sleep_events: &Receiver<Sleep>, jumps: &mut JumpDetector, clock: &dyn Clock) -> bool {
+    let mut resumed = jumps.check(clock).is_some();
+    for event in sleep_events.try_iter() {
+        resumed |= event == Sleep::Resumed;
+    }
+    resumed
+}
+
+/// Gets `dat` ready to go out again once we've `resumed`, or the MCU needs a `reload`.
+fn recover(dat: &mut SharedAppData, resumed: bool, reload: bool, clock: &dyn Clock) {
+    if resumed {
+        dat.forget_wall_clock(clock.now());
+    }
+    if resumed || reload {
+        dat.resync();
+    }
+}
 
-pub fn update_thread(arc: Arc<Mutex<SharedAppData>>) -> Result<()> {
+pub fn update_thread(
+    arc: Arc<Mutex<SharedAppData>>,
+    clock: Box<dyn Clock>,
+    sleep_events: Receiver<Sleep>,
+) -> Result<()> {
     let mut port = open_serial();
     let mut status_buf = vec![0u8; 1];
+    let mut calendars = calendar::Watcher::default();
+    let mut idle = idle::Watcher::new(idle::SystemProbe::default());
+    // Whether the MCU might have lost what we told it, which it has right after we start.
+    let mut reload = true;
+    // When we last handed the MCU a segment.
+    let mut loaded_at: Option<Instant> = None;
+    let mut jumps = JumpDetector::default();
     loop {
+        let resumed = resumed(&sleep_events, &mut jumps, &*clock);
+        if resumed {
+            // The USB device most likely went away and came back.
+            port = open_serial();
+            status_buf[0] = 0;
+            calendars = calendar::Watcher::default();
+            reload = true;
+        }
+
         // Parse status_buf
-        let animation_running = status_buf[0] != 0;
+        let animation_running = status_buf[0] & 1 != 0;
+        if status_buf[0] & 2 != 0 {
+            // The MCU reset under us.
+            status_buf[0] &= !2;
+            reload = true;
+        }
 
         // What we're going to send over the wire.
         let (realtime, serial_data) = {
@@ -28,51 +99,50 @@ pub fn update_thread(arc: Arc<Mutex<SharedAppData>>) -> Result<()> {
             // perf in normal cases too, but nope.
             let mut dat = arc.lock().unwrap();
 
+            recover(&mut dat, resumed, reload, &*clock);
+            reload = false;
+
+            trigger::run_due(&mut dat, clock.now());
+            calendars.run_due(&mut dat, clock.now());
+            idle.run(&mut dat);
+
+            // On the way into a scene.
+            if let Some(strips) = dat.fade_frame() {
+                dat.strips = strips;
+                dat.strips_changed = true;
+            }
+
             // Mode-specific logic
             {
-                match dat.controller {
-                    Controller::Manual => {}
-                    Controller::Wave {
-                        started_at,
-                        interval_ms,
-                        warm,
-                        cold,
-                        ty,
-                    } => {
-                        let pos = started_at.elapsed().as_millis() as f32 / interval_ms;
-                        let pos_mod = pos % 1.0;
-                        let u16_max = f32::from(u16::MAX);
-                        let u16_halfmax = u16_max / 2.0;
-                        let val = match ty {
-                            WaveType::Sine => pos.sin() * u16_halfmax + u16_halfmax,
-                            WaveType::Square(duty) => {
-                                if pos_mod < duty {
-                                    u16_max
-                                } else {
-                                    0.0
-                                }
-                            }
-                        } as u16;
-
-                        if cold {
-                            dat.strips[0].0 = val;
-                            dat.strips[1].0 = val;
-                        }
-                        if warm {
-                            dat.strips[0].1 = val;
-                            dat.strips[1].1 = val;
-                        }
+                let dat = &mut *dat;
+                if let Some(strips) =
+                    dat.controller
+                        .output(clock.now(), &dat.strips, dat.relay_enabled)
+                {
+                    if strips != dat.strips {
+                        dat.strips = strips;
+                        // Realtime output gets pushed regardless.
+                        dat.strips_changed |= !dat.controller.realtime();
                     }
-                };
+                }
             }
 
             let mut out = Vec::with_capacity(24);
 
+            // Nobody's around to see it at full brightness.
+            let dim = dat.idle_dim();
+            let caps = dat.caps.clone();
             let push_strips = |strips: &[Strip], out: &mut Vec<u8>| {
+                let dim = |value: u16, cap: u16| (f32::from(value.min(cap)) * dim) as u16;
                 strips
                     .iter()
                     // Invert, so our 0 is no light
-                    .map(|strip| vec![u16::MAX - strip.0, u16::MAX - strip.1])
+                    .map(|strip| {
+                        vec![
+                            u16::MAX - dim(strip.0, caps.0),
+                            u16::MAX - dim(strip.1, caps.1),
+                        ]
+                    })
                     // Convert to big endian bytes
                     .flat_map(|words| {
                         words
@@ -86,82 +156,197 @@ pub fn update_thread(arc: Arc<Mutex<SharedAppData>>) -> Result<()> {
 
             // out.push(0x3); //IDebugEnable
 
-            'schedule: {
+            // A notification or a preview has the lights to itself while it plays.
+            let mut previewing = false;
+            if let Some(frame) = dat.notification_frame(clock.now()) {
+                // Keep the MCU from lerping over it, it gets its transition back afterwards.
+                out.push(0x4); // INoInterpolate
+                out.push(0x1); // IImmediate
+                push_strips(&frame, &mut out); // [Strip]
+                realtime = true;
+                previewing = true;
+            } else if let Some(preview) = &dat.preview {
+                match preview.frame() {
+                    Some(frame) => {
+                        // Keep a loaded schedule from kicking in halfway.
+                        out.push(0x4); // INoInterpolate
+                        out.push(0x1); // IImmediate
+                        push_strips(&frame, &mut out); // [Strip]
+                        realtime = true;
+                        previewing = true;
+                    }
+                    None => dat.stop_preview(),
+                }
+            }
+
+            // Which schedule (if any) has the lights, and whether it already pushed an
+            // eased frame for this round.
+            let mut driving = None;
+            let mut streamed = false;
+
+            if !previewing {
+                let dat = &mut *dat;
+                let now = clock.now();
+
+                // Recurring schedules re-arm themselves, including right after a run.
+                for schedule in &mut dat.schedules {
+                    if schedule.enabled && schedule.recurrence.enabled && schedule.send.is_none() {
+                        schedule.send = Some(now);
+                        schedule.status_changed = true;
+                    }
+                }
+
                 // TODO: Parse these in the UI and have `Duration`s ready to go here.
                 // this is hacky because its not meant to be here, unsurprisingly
-                let sched_start_res = humantime::parse_duration(&dat.schedule.start.0);
-                let sched_length_res = humantime::parse_duration(&dat.schedule.length.0);
+                let timelines = schedule::timelines(&mut dat.schedules);
+                let windows = schedule::windows(&dat.schedules, &timelines);
+                let pick = schedule::pick(&windows, now).map(|pick| pick.index);
 
-                dat.schedule.start.1 = sched_start_res.clone().err().map(|_| ());
-                dat.schedule.length.1 = sched_length_res.clone().err().map(|_| ());
-                if sched_start_res.is_err() || sched_length_res.is_err() {
-                    break 'schedule;
+                // The MCU only has room for one of them. Whatever lost out after beginning is
+                // dropped, the rest will need to be loaded again if they get picked later.
+                for (i, schedule) in dat.schedules.iter_mut().enumerate() {
+                    if pick == Some(i) {
+                        continue;
+                    }
+                    if windows.iter().any(|w| w.index == i && w.begin <= now) {
+                        schedule.send = None;
+                    }
+                    schedule.status_changed = true;
                 }
 
-                let sched_start = sched_start_res?;
-                let sched_length = sched_length_res?;
-
-                // Reconcile with MCU, maybe swap if animation stopped (probably ended, we hope)
-                //
-                // - This has to run before we start a new animation.
-                // - We're not guaranteed timing for animation_running updates
-                //
+                if let (Some(i), Some(segments)) = (pick, pick.and_then(|i| timelines[i].as_ref()))
                 {
-                    if dat.schedule.send.is_some_and(|t| {
-                        SystemTime::now().duration_since(t).is_ok_and(|dur| {
-                            dur > ((sched_start + sched_length).saturating_sub(
-                                Duration::from_millis(if sched_length > Duration::from_secs(10) {
-                                    250
-                                } else {
-                                    0
-                                }),
-                            ))
-                        })
-                    }) && !animation_running
+                    let schedule = &mut dat.schedules[i];
+                    let last = segments.len() - 1;
+                    let elapsed = schedule.send.and_then(|t| now.duration_since(t).ok());
+
+                    // Reconcile with MCU, maybe swap if animation stopped (probably ended, we hope)
+                    //
+                    // - This has to run before we start a new animation.
+                    // - We're not guaranteed timing for animation_running updates
+                    //
+                    // - After a reload there's nothing on the MCU to wait for, so a schedule
+                    //   that ended while we weren't looking just jumps to its end.
                     {
-                        // Swap
-                        if dat.schedule.swap_on_stop {
-                            let prev = dat.strips.clone();
-                            dat.strips = dat.schedule.endpoint.clone();
-                            dat.schedule.endpoint = prev;
+                        let ended = elapsed.is_some_and(|dur| segments[last].reached(dur));
+                        if ended
+                            && (schedule.status_changed
+                                || (schedule.segment >= last && !animation_running))
+                        {
+                            // Swap
+                            if schedule.swap_on_stop {
+                                let prev = dat.strips.clone();
+                                if let Some(endpoint) = schedule.endpoint_mut() {
+                                    dat.strips = std::mem::replace(endpoint, prev);
+                                }
+                            }
+                            // Sync that we're no longer running
+                            schedule.send = None;
+                            schedule.recurrence.skip_next = false;
                         }
-                        // Sync that we're no longer running
-                        dat.schedule.send = None;
                     }
-                }
 
-                {
-                    if dat.schedule.status_changed && dat.schedule.send.is_some() {
-                        out.push(0x2); // IInterpolateFrame
+                    // Which segment (if any) the MCU needs to be told about this round.
+                    let mut load = None;
+                    if schedule.send.is_none() {
+                        // Just finished.
+                    } else if schedule.status_changed {
+                        let elapsed = elapsed.unwrap_or_default();
+                        schedule.segment = segments
+                            .iter()
+                            .position(|segment| !segment.reached(elapsed))
+                            .unwrap_or(last);
+                        load = Some(schedule.segment);
+                    } else if let Some(elapsed) = elapsed {
+                        // Move on once the previous segment is done with.
+                        let current = &segments[schedule.segment.min(last)];
+                        if schedule.segment < last
+                            && current.reached(elapsed)
+                            && (!animation_running || !current.easing.device_native())
+                        {
+                            schedule.segment += 1;
+                            load = Some(schedule.segment);
+                        } else if current.easing.device_native()
+                            && !current.reached(elapsed)
+                            && loaded_at.is_some_and(|at| at.elapsed() >= RESYNC)
+                        {
+                            // The MCU's millis() drifts from our clock, which adds up over
+                            // hours. Hand it the segment again, with the offsets corrected.
+                            load = Some(schedule.segment);
+                        }
+                    }
+                    if load.is_some() {
+                        loaded_at = Some(Instant::now());
+                    }
+
+                    {
+                        if let Some(i) = load {
+                            let segment = &segments[i];
+                            if segment.easing.device_native() {
+                                let elapsed = elapsed.unwrap_or_default();
+                                let (delay, length) = if elapsed > segment.begin {
+                                    // Loading it halfway through (after a restart, say), so
+                                    // pick up from where it should be by now.
+                                    out.push(0x1); // IImmediate
+                                    push_strips(
+                                        &schedule::state_at(segments, &dat.strips, elapsed),
+                                        &mut out,
+                                    ); // [Strip]
+                                    (Duration::ZERO, segment.end().saturating_sub(elapsed))
+                                } else {
+                                    if i > 0 {
+                                        // The MCU lerps from the last IImmediate, so hand it
+                                        // wherever the previous segment left off.
+                                        out.push(0x1); // IImmediate
+                                        push_strips(&segments[i - 1].target, &mut out);
+                                        // [Strip]
+                                    }
+                                    (segment.begin - elapsed, segment.length)
+                                };
+                                out.push(0x2); // IInterpolateFrame
+
+                                // Both fit, timelines past schedule::MAX_SPAN don't get this far.
+                                out.extend_from_slice(&millis(delay).to_be_bytes());
+                                out.extend_from_slice(&millis(length).to_be_bytes());
 
-                        out.extend_from_slice(
-                            &u32::try_from(sched_start.as_millis())?.to_be_bytes(),
-                        );
-                        out.extend_from_slice(
-                            &u32::try_from(sched_length.as_millis())?.to_be_bytes(),
-                        );
+                                push_strips(&segment.target, &mut out); // [Strip]
+                            } else {
+                                // We render this one ourselves, don't let the MCU fight us over it.
+                                out.push(0x4); // INoInterpolate
+                            }
+                        }
 
-                        push_strips(&dat.schedule.endpoint, &mut out); // [Strip]
+                        schedule.status_changed = false;
                     }
 
-                    if dat.schedule.send.is_none() {
-                        out.push(0x4); // INoInterpolate
+                    driving = schedule.send.map(|_| i);
+
+                    // The MCU can only lerp, so anything fancier gets streamed as IImmediate frames.
+                    if let (Some(elapsed), Some(_)) = (elapsed, schedule.send) {
+                        let segment = &segments[schedule.segment.min(last)];
+                        if !segment.easing.device_native() && elapsed >= segment.begin {
+                            let frame = schedule::state_at(segments, &dat.strips, elapsed);
+
+                            out.push(0x1); // IImmediate
+                            push_strips(&frame, &mut out); // [Strip]
+                            realtime = true;
+                            streamed = true;
+                        }
                     }
+                }
 
-                    dat.schedule.status_changed = false;
+                if driving.is_none() {
+                    out.push(0x4); // INoInterpolate
                 }
-            };
+            }
 
             // Selectively push live light data (:
-            if match dat.controller {
-                Controller::Manual if dat.schedule.send.is_none() => true,
-                Controller::Manual if dat.strips_changed => {
-                    dat.strips_changed = false;
-                    true
-                }
-                Controller::Wave { .. } => true,
-                Controller::Manual => false,
-            } {
+            if !previewing
+                && !streamed
+                && (dat.controller.realtime()
+                    || driving.is_none()
+                    || std::mem::take(&mut dat.strips_changed))
+            {
                 out.push(0x1); // IImmediate
                 push_strips(&dat.strips, &mut out); // [Strip]
                 realtime = true;
@@ -195,6 +380,8 @@ pub fn update_thread(arc: Arc<Mutex<SharedAppData>>) -> Result<()> {
             // try reopening the port after a bit..
             sleep(Duration::from_millis(50));
             port = open_serial();
+            // Could be a whole different (or freshly reset) MCU on the other end now.
+            reload = true;
 
             tries += 1;
         }
@@ -211,3 +398,91 @@ pub fn update_thread(arc: Arc<Mutex<SharedAppData>>) -> Result<()> {
         }
     }
 }
+
+fn millis(dur: Duration) -> u32 {
+    u32::try_from(dur.as_millis()).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::{clock::tests::FakeClock, timespec::tests::at, ScheduleUi};
+
+    /// The start of a round of the update thread, up to where it has the MCU reload.
+    fn round(
+        dat: &mut SharedAppData,
+        sleep_events: &Receiver<Sleep>,
+        jumps: &mut JumpDetector,
+        clock: &FakeClock,
+    ) -> bool {
+        let resumed = resumed(sleep_events, jumps, clock);
+        recover(dat, resumed, false, clock);
+        resumed
+    }
+
+    fn settled(dat: &mut SharedAppData) {
+        for schedule in &mut dat.schedules {
+            schedule.status_changed = false;
+        }
+        dat.strips_changed = false;
+    }
+
+    fn reloading(dat: &SharedAppData) -> bool {
+        dat.strips_changed && dat.schedules.iter().all(|s| s.status_changed)
+    }
+
+    #[test]
+    fn resyncs_after_clock_jumps() {
+        let clock = FakeClock::new(at("2023-07-14T12:00:00Z"));
+        let (_tx, rx) = mpsc::channel();
+        let mut jumps = JumpDetector::default();
+        let mut dat = SharedAppData::new();
+        dat.schedules = vec![ScheduleUi::default(), ScheduleUi::default()];
+        assert!(!round(&mut dat, &rx, &mut jumps, &clock));
+
+        let mut check = |clock: &FakeClock, want: bool| {
+            settled(&mut dat);
+            assert_eq!(round(&mut dat, &rx, &mut jumps, clock), want);
+            assert_eq!(reloading(&dat), want);
+        };
+
+        // The loop runs late now and then, that's not a jump.
+        clock.tick(Duration::from_millis(5));
+        clock.skip(Duration::from_millis(1900));
+        check(&clock, false);
+        clock.rewind(Duration::from_millis(1900));
+        check(&clock, false);
+
+        clock.skip(Duration::from_secs(3));
+        check(&clock, true);
+        clock.tick(Duration::from_secs(1));
+        check(&clock, false);
+        clock.rewind(Duration::from_secs(3));
+        check(&clock, true);
+        // Hours of suspend.
+        clock.skip(Duration::from_secs(8 * 3600));
+        check(&clock, true);
+    }
+
+    #[test]
+    fn resyncs_after_logind_resumes() {
+        let clock = FakeClock::new(at("2023-07-14T12:00:00Z"));
+        let (tx, rx) = mpsc::channel();
+        let mut jumps = JumpDetector::default();
+        let mut dat = SharedAppData::new();
+        dat.schedules = vec![ScheduleUi::default()];
+        round(&mut dat, &rx, &mut jumps, &clock);
+
+        settled(&mut dat);
+        tx.send(Sleep::Suspending).unwrap();
+        assert!(!round(&mut dat, &rx, &mut jumps, &clock));
+        assert!(!reloading(&dat));
+
+        // A quick suspend the clocks don't show.
+        tx.send(Sleep::Resumed).unwrap();
+        assert!(round(&mut dat, &rx, &mut jumps, &clock));
+        assert!(reloading(&dat));
+    }
+}
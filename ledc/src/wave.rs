@@ -0,0 +1,489 @@
+use std::{
+    f64::consts::TAU,
+    mem,
+    time::{Duration, SystemTime},
+};
+
+use eframe::egui::{ComboBox, Slider, Ui};
+use serde::{Deserialize, Serialize};
+
+use crate::{controller::Controller, Strip, WaveType};
+
+/// Sweeps each channel of each strip up and down, around a base level.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct Wave {
+    #[serde(skip)]
+    #[serde(default = "SystemTime::now")] // gets thrown away anyway
+    pub started_at: SystemTime,
+    /// Set with the sliders, the waves swing around this.
+    pub base: Vec<Strip>,
+    /// Cold and warm, for each strip.
+    pub channels: Vec<[Channel; 2]>,
+}
+
+/// The wave on one channel of one strip.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct Channel {
+    pub enabled: bool,
+    pub ty: WaveType,
+    pub interval_ms: f32,
+    /// Where the wave bottoms and tops out, counted from the base level.
+    pub min: i32,
+    pub max: i32,
+    /// How far ahead of the others this runs, in periods.
+    pub phase: f32,
+}
+
+impl Channel {
+    fn new(enabled: bool) -> Self {
+        Channel {
+            enabled,
+            ty: WaveType::Sine,
+            interval_ms: 1000.0,
+            min: 0,
+            max: i32::from(u16::MAX),
+            phase: 0.0,
+        }
+    }
+
+    /// How many periods in the wave is `elapsed` into it, counting the phase.
+    fn position(&self, elapsed: Duration) -> f64 {
+        elapsed.as_secs_f64() * 1000.0 / f64::from(self.interval_ms) + f64::from(self.phase)
+    }
+
+    /// The channel's value `elapsed` into the wave, on top of `base`.
+    pub fn level(&self, base: u16, elapsed: Duration) -> u16 {
+        if !self.enabled {
+            return base;
+        }
+        let pos = self.position(elapsed);
+        let offset =
+            f64::from(self.min) + (f64::from(self.max) - f64::from(self.min)) * self.ty.shape(pos);
+        (f64::from(base) + offset).clamp(0.0, f64::from(u16::MAX)) as u16
+    }
+
+    /// Returns whether the timing changed.
+    fn ui(&mut self, ui: &mut Ui, elapsed: Option<Duration>) -> bool {
+        ui.checkbox(&mut self.enabled, "enabled");
+        ComboBox::from_id_source("type")
+            .selected_text(self.ty.name())
+            .show_ui(ui, |ui| {
+                for ty in WaveType::ALL {
+                    let selected = mem::discriminant(&self.ty) == mem::discriminant(&ty);
+                    if ui.selectable_label(selected, ty.name()).clicked() && !selected {
+                        self.ty = ty;
+                    }
+                }
+            });
+        if let WaveType::Square(duty) = &mut self.ty {
+            ui.add(Slider::new(duty, 0.0..=1.0).text("duty"));
+        }
+        let changed = ui
+            .add(
+                Slider::new(&mut self.interval_ms, 5.0..=100_000.0)
+                    .text("interval")
+                    .logarithmic(true),
+            )
+            .changed();
+        if let Some(elapsed) = elapsed {
+            let mut immut_pos = self.position(elapsed).rem_euclid(1.0) as f32 * self.interval_ms;
+            ui.add_enabled(
+                false,
+                Slider::new(&mut immut_pos, 0.0..=self.interval_ms).text("current"),
+            );
+        }
+        let range = -i32::from(u16::MAX)..=i32::from(u16::MAX);
+        ui.add(Slider::new(&mut self.min, range.clone()).text("min"));
+        ui.add(Slider::new(&mut self.max, range).text("max"));
+        ui.add(Slider::new(&mut self.phase, 0.0..=1.0).text("phase"));
+        changed
+    }
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Self::new(true)
+    }
+}
+
+impl Default for Wave {
+    fn default() -> Self {
+        Wave {
+            started_at: SystemTime::now(),
+            base: vec![Strip(0, 0); 2],
+            channels: vec![[Channel::new(false), Channel::new(true)]; 2],
+        }
+    }
+}
+
+impl WaveType {
+    /// One of each, for picking from.
+    pub const ALL: [WaveType; 6] = [
+        WaveType::Sine,
+        WaveType::Square(0.1),
+        WaveType::Triangle,
+        WaveType::Sawtooth,
+        WaveType::Breathe,
+        WaveType::Noise,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WaveType::Sine => "Sine",
+            WaveType::Square(_) => "Square",
+            WaveType::Triangle => "Triangle",
+            WaveType::Sawtooth => "Sawtooth",
+            WaveType::Breathe => "Breathe",
+            WaveType::Noise => "Noise",
+        }
+    }
+
+    /// The wave from 0 to 1, `pos` periods in.
+    pub fn shape(self, pos: f64) -> f64 {
+        let phase = pos.rem_euclid(1.0);
+        match self {
+            WaveType::Sine => 0.5 + 0.5 * (TAU * phase).sin(),
+            WaveType::Square(duty) => {
+                if phase < f64::from(duty) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            WaveType::Triangle => 1.0 - (2.0 * phase - 1.0).abs(),
+            WaveType::Sawtooth => phase,
+            WaveType::Breathe => {
+                // exp(sin) is the usual breathing LED curve, scaled to 0..1 and
+                // shifted so it starts at the bottom.
+                let e = std::f64::consts::E;
+                ((TAU * phase - TAU / 4.0).sin().exp() - 1.0 / e) / (e - 1.0 / e)
+            }
+            WaveType::Noise => {
+                let n = pos.floor();
+                let t = pos - n;
+                let smooth = t * t * (3.0 - 2.0 * t);
+                let (a, b) = (noise(n as i64), noise(n as i64 + 1));
+                a + (b - a) * smooth
+            }
+        }
+    }
+}
+
+/// A repeatable random value from 0 to 1 for each `n`.
+fn noise(n: i64) -> f64 {
+    // splitmix64's finalizer
+    let mut x = (n as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^= x >> 31;
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+impl Wave {
+    /// Everything but the running state, shared with the trigger action editor.
+    /// Shows where each wave's at when given how long it's been running.
+    /// Returns whether the timing changed.
+    pub fn settings_ui(&mut self, ui: &mut Ui, elapsed: Option<Duration>) -> bool {
+        let mut changed = false;
+        for (i, channels) in self.channels.iter_mut().enumerate() {
+            ui.push_id(("wave_strip", i), |ui| {
+                ui.group(|ui| {
+                    ui.label(format!("Strip {i}"));
+                    ui.horizontal(|ui| {
+                        for (channel, name) in channels.iter_mut().zip(["cold", "warm"]) {
+                            ui.push_id(name, |ui| {
+                                ui.vertical(|ui| {
+                                    ui.label(name);
+                                    changed |= channel.ui(ui, elapsed);
+                                });
+                            });
+                        }
+                    });
+                });
+            });
+        }
+        changed
+    }
+}
+
+impl Controller for Wave {
+    fn name(&self) -> &'static str {
+        "Wave"
+    }
+
+    fn output(&mut self, at: SystemTime, strips: &[Strip], _relay: bool) -> Option<Vec<Strip>> {
+        let elapsed = at.duration_since(self.started_at).unwrap_or_default();
+        let mut strips = strips.to_vec();
+        for ((strip, base), [cold, warm]) in strips.iter_mut().zip(&self.base).zip(&self.channels) {
+            *strip = Strip(cold.level(base.0, elapsed), warm.level(base.1, elapsed));
+        }
+        Some(strips)
+    }
+
+    fn realtime(&self) -> bool {
+        true
+    }
+
+    fn params(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("base", format!("{:?}", self.base)),
+            ("channels", format!("{:?}", self.channels)),
+        ]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.group(|ui| {
+            ui.label("Slide controls");
+            let elapsed = self.started_at.elapsed().unwrap_or_default();
+            if self.settings_ui(ui, Some(elapsed)) {
+                self.started_at = SystemTime::now();
+            }
+        });
+    }
+
+    fn strips_touched(&mut self, strips: &[Strip]) {
+        self.base = strips.to_vec();
+    }
+
+    fn save(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+}
+
+/// A warm, flame-like flicker: a few layers of noise, with the odd gust.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct Flicker {
+    #[serde(skip)]
+    #[serde(default = "SystemTime::now")] // gets thrown away anyway
+    pub started_at: SystemTime,
+    /// Where the flame sits, 0 to 1.
+    pub base: f32,
+    /// How far it wavers from there, 0 to 1.
+    pub intensity: f32,
+    /// 1 is a candle in still air.
+    pub speed: f32,
+    pub warm: bool,
+    pub cold: bool,
+}
+
+impl Default for Flicker {
+    fn default() -> Self {
+        Flicker {
+            started_at: SystemTime::now(),
+            base: 0.6,
+            intensity: 0.3,
+            speed: 1.0,
+            warm: true,
+            cold: false,
+        }
+    }
+}
+
+impl Flicker {
+    /// How bright the flame on strip `i` is `t` seconds in, 0 to 1.
+    ///
+    /// Each strip reads its own stretch of the noise, so they move like separate flames.
+    pub fn level(&self, i: usize, t: f64) -> f64 {
+        let t = t * f64::from(self.speed);
+        let noise = |hz: f64, layer: usize| {
+            WaveType::Noise.shape(t * hz + (i * 7919 + layer * 104_729) as f64) * 2.0 - 1.0
+        };
+        // (frequency in Hz, weight), slow sway to fast shimmer.
+        let sway = 0.5 * noise(0.7, 0) + 0.3 * noise(2.3, 1) + 0.2 * noise(7.1, 2);
+        // Every now and then the air moves, and the flame dips and gutters.
+        let gust = ((noise(0.15, 3) - 0.5) / 0.5).max(0.0);
+        let intensity = f64::from(self.intensity);
+        let level = f64::from(self.base) + intensity * sway * (1.0 + 2.0 * gust) - intensity * gust;
+        level.clamp(0.0, 1.0)
+    }
+}
+
+impl Controller for Flicker {
+    fn name(&self) -> &'static str {
+        "Flicker"
+    }
+
+    fn output(&mut self, at: SystemTime, strips: &[Strip], _relay: bool) -> Option<Vec<Strip>> {
+        let t = at
+            .duration_since(self.started_at)
+            .unwrap_or_default()
+            .as_secs_f64();
+        // The caps are the update loop's business.
+        let scale = |level: f64, on: bool| {
+            if on {
+                (level * f64::from(u16::MAX)) as u16
+            } else {
+                0
+            }
+        };
+        Some(
+            (0..strips.len())
+                .map(|i| {
+                    let level = self.level(i, t);
+                    Strip(scale(level, self.cold), scale(level, self.warm))
+                })
+                .collect(),
+        )
+    }
+
+    fn realtime(&self) -> bool {
+        true
+    }
+
+    fn params(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("base", self.base.to_string()),
+            ("intensity", self.intensity.to_string()),
+            ("speed", self.speed.to_string()),
+            ("warm", self.warm.to_string()),
+            ("cold", self.cold.to_string()),
+        ]
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.group(|ui| {
+            ui.label("Flicker");
+            ui.add(Slider::new(&mut self.base, 0.0..=1.0).text("base"));
+            ui.add(Slider::new(&mut self.intensity, 0.0..=1.0).text("intensity"));
+            ui.add(
+                Slider::new(&mut self.speed, 0.1..=10.0)
+                    .text("speed")
+                    .logarithmic(true),
+            );
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.warm, "warm");
+                ui.checkbox(&mut self.cold, "cold");
+            });
+        });
+    }
+
+    fn save(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn shapes_repeat_every_period() {
+        for ty in WaveType::ALL {
+            for i in 0..40 {
+                let pos = f64::from(i) * 0.137 - 2.0;
+                let shape = ty.shape(pos);
+                assert!((0.0..=1.0).contains(&shape), "{ty:?} at {pos}: {shape}");
+                if ty == WaveType::Noise {
+                    // A new level every period, but no jumps between them.
+                    let n = pos.floor();
+                    assert!(close(ty.shape(n + 1.0 - 1e-12), ty.shape(n + 1.0)));
+                } else {
+                    assert!(close(shape, ty.shape(pos + 1.0)), "{ty:?} at {pos}");
+                    assert!(close(shape, ty.shape(pos + 3.0)), "{ty:?} at {pos}");
+                }
+            }
+        }
+        assert!(close(WaveType::Sine.shape(0.25), 1.0));
+        assert!(close(WaveType::Breathe.shape(0.0), 0.0));
+        assert!(close(WaveType::Breathe.shape(0.5), 1.0));
+    }
+
+    #[test]
+    fn levels_repeat_every_interval() {
+        let mut channel = Channel::new(true);
+        channel.interval_ms = 1500.0;
+        for ty in [WaveType::Sine, WaveType::Square(0.3), WaveType::Sawtooth] {
+            channel.ty = ty;
+            for ms in [0, 100, 700, 1200] {
+                let at = Duration::from_millis(ms);
+                let level = channel.level(0, at);
+                // Give or take the rounding.
+                for later in [1500, 4500] {
+                    let again = channel.level(0, at + Duration::from_millis(later));
+                    assert!(level.abs_diff(again) <= 1, "{ty:?} at {ms} ms");
+                }
+            }
+        }
+        channel.ty = WaveType::Sawtooth;
+        assert_eq!(channel.level(0, Duration::from_millis(750)), u16::MAX / 2);
+    }
+
+    #[test]
+    fn phase_shifts_the_wave() {
+        let mut ahead = Channel::new(true);
+        ahead.phase = 0.5;
+        let behind = Channel::new(true);
+        for ms in [0, 250, 400, 900] {
+            let at = Duration::from_millis(ms);
+            let half = Duration::from_millis(ms + 500);
+            assert_eq!(ahead.level(0, at), behind.level(0, half), "{ms} ms");
+            // A sine half a period on is mirrored around the middle.
+            let sum = u32::from(ahead.level(0, at)) + u32::from(behind.level(0, at));
+            assert!(sum.abs_diff(u32::from(u16::MAX)) <= 1, "{ms} ms: {sum}");
+        }
+        assert!((ahead.position(Duration::ZERO) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn output_swings_around_the_base() {
+        let mut wave = Wave {
+            started_at: SystemTime::UNIX_EPOCH,
+            base: vec![Strip(1000, 60_000), Strip(500, 0)],
+            ..Wave::default()
+        };
+        for channel in wave.channels.iter_mut().flatten() {
+            channel.ty = WaveType::Square(0.5);
+            channel.min = -2000;
+            channel.max = 10_000;
+        }
+        // The first strip's cold channel is left alone.
+        wave.channels[0][0].enabled = false;
+        wave.channels[1][0].enabled = true;
+
+        let mut at = |ms| {
+            let at = SystemTime::UNIX_EPOCH + Duration::from_millis(ms);
+            wave.output(at, &[Strip(7, 7), Strip(7, 7), Strip(7, 7)], false)
+        };
+        // Up at the top, clamped short of overflowing.
+        assert_eq!(
+            at(100),
+            Some(vec![
+                Strip(1000, u16::MAX),
+                Strip(10_500, 10_000),
+                Strip(7, 7)
+            ])
+        );
+        // Down at the bottom, clamped short of going negative.
+        assert_eq!(
+            at(600),
+            Some(vec![Strip(1000, 58_000), Strip(0, 0), Strip(7, 7)])
+        );
+    }
+
+    #[test]
+    fn leaves_out_fields_in_the_config() {
+        let wave: Wave = ron::from_str("(channels: [((min: -5), (ty: Triangle))])").unwrap();
+        let [cold, warm] = &wave.channels[0];
+        assert_eq!(cold.min, -5);
+        assert_eq!(cold.max, i32::from(u16::MAX));
+        assert!(cold.enabled);
+        assert_eq!(warm.ty, WaveType::Triangle);
+        assert_eq!(warm.interval_ms, 1000.0);
+        assert_eq!(wave.base, Wave::default().base);
+    }
+}
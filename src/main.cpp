@@ -46,6 +46,8 @@ uint32_t animStart;
 ledState animStartState = {0,0,0,0};
 uint32_t animLength;
 ledState animEndState = {0,0,0,0};
+// Cleared by the first IReadStatus, so the host can tell we've reset and lost our state.
+bool freshBoot = true;
 
 const float TAU = PI * 2;
 const uint16_t U16_MAX = 65535;
@@ -162,7 +164,9 @@ void handle_command()
 			digitalWrite(relay_pin, Serial.read() == 0 ? LOW : HIGH);
 		} else if (inst == IReadStatus) {
 			// No logs allowed here. This should be called last in the host R/W cycle.
-			Serial.write(animValid);
+			// bit 0: animation running, bit 1: we've reset since the last status read
+			Serial.write(animValid | (freshBoot << 1));
+			freshBoot = false;
 		} else {
 			dbgln("I??");
 		}
@@ -174,7 +178,9 @@ void interpolate() {
 		return;
 	dbgln("animValid %d: animStart=%d,animLength=%d,now=%d", animValid,animStart,animLength,millis());
 	uint32_t end = animStart + animLength;
-	double progress = ((double)millis() - (double)end) / (double)animLength / 2 + 0.5;
+	// Runs start to end over exactly animLength, which the host's eased segments are timed
+	// against. Firmware from before ledc's easing took twice that, so flash both together.
+	double progress = animLength == 0 ? 1.0 : ((double)millis() - (double)animStart) / (double)animLength;
 	if (progress<0.0) progress=0;
 	if (progress>1.0) progress=1.0;
 	dbgln("progress=%f,end=%d", progress,end);